@@ -0,0 +1,146 @@
+use crate::ci_cd::{ChannelId, PipelineName, RepoName, RunContext, RunId};
+use anyhow::{bail, Result};
+use crossbeam::channel::{unbounded, Sender, TryRecvError};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::sleep;
+
+/// how long an approval waits by default before the run is rejected, a day.
+pub const DEFAULT_APPROVAL_TIMEOUT: u64 = 24 * 60 * 60;
+
+/// makes the pipeline an approval job. instead of running steps it pauses the run until someone
+/// approves or rejects it from discord.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Approval {
+    /// names or ids of the discord roles allowed to decide. anyone can if not set.
+    pub roles: Option<Vec<String>>,
+    /// seconds to wait for a decision before rejecting. defaults to a day.
+    pub timeout: Option<u64>,
+    /// shown on the approval message, e.g. what's about to be deployed.
+    pub message: Option<String>,
+}
+
+impl Approval {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_APPROVAL_TIMEOUT))
+    }
+
+    /// whether someone with these roles (ids and names) may decide.
+    pub fn allows(&self, roles: &[(String, String)]) -> bool {
+        match &self.roles {
+            Some(allowed) => roles
+                .iter()
+                .any(|(id, name)| allowed.iter().any(|role| role == id || role == name)),
+            None => true,
+        }
+    }
+}
+
+/// someone approving or rejecting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub approved: bool,
+    pub by: String,
+}
+
+/// an approval job asking discord for a decision.
+#[derive(Debug, Clone)]
+pub struct ApprovalRequest {
+    pub run_id: RunId,
+    pub job: PipelineName,
+    pub repo: RepoName,
+    pub branch: String,
+    pub commit_sha: String,
+    pub triggered_by: String,
+    /// where to ask.
+    pub channel_id: ChannelId,
+    pub approval: Approval,
+    /// unix time the run is rejected at.
+    pub deadline: u64,
+    pub decision: Sender<Decision>,
+}
+
+impl ApprovalRequest {
+    /// `<run_id>:<job>`, what the approval buttons are told apart by.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.run_id, self.job)
+    }
+}
+
+/// how an approval job was decided, kept in the run history.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApprovalRecord {
+    pub approved: bool,
+    /// who decided. not set if nobody did before the timeout.
+    pub by: Option<String>,
+    /// unix time.
+    pub decided_at: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+/// asks for approval of the runs `job` through `requests`, in the channel the run was started
+/// from, and waits for someone to decide. a timeout is a rejection.
+pub async fn wait_for_decision(
+    requests: &Sender<ApprovalRequest>,
+    ctx: &RunContext,
+    job: &str,
+    approval: &Approval,
+) -> Result<ApprovalRecord> {
+    let Some(channel_id) = ctx.channel_id else {
+        bail!("the run wasn't started from a discord channel to ask for approval in");
+    };
+
+    let (tx, rx) = unbounded();
+    let timeout = approval.timeout();
+    let deadline = Instant::now() + timeout;
+
+    if requests
+        .send(ApprovalRequest {
+            run_id: ctx.run_id,
+            job: job.to_string(),
+            repo: ctx.repo.repo_name.clone(),
+            branch: ctx.branch.clone(),
+            commit_sha: ctx.commit_sha.clone(),
+            triggered_by: ctx.triggered_by.clone(),
+            channel_id,
+            approval: approval.clone(),
+            deadline: now() + timeout.as_secs(),
+            decision: tx,
+        })
+        .is_err()
+    {
+        bail!("approvals can't be asked for at the moment");
+    }
+
+    loop {
+        match rx.try_recv() {
+            Ok(Decision { approved, by }) => {
+                return Ok(ApprovalRecord {
+                    approved,
+                    by: Some(by),
+                    decided_at: now(),
+                })
+            }
+            Err(TryRecvError::Disconnected) => bail!("the approval request was dropped"),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(ApprovalRecord {
+                approved: false,
+                by: None,
+                decided_at: now(),
+            });
+        }
+
+        sleep(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,241 @@
+use crate::ci_cd::{PipelineName, RunId, STATE_DIR};
+use anyhow::{bail, Result};
+use glob::glob;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{copy, create_dir_all, read_to_string, write, File},
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+pub const ARTIFACTS_DIR: &str = "artifacts";
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// artifacts up to this size are attached to the discord completion message.
+pub const ATTACH_LIMIT: u64 = 8 * 1024 * 1024;
+/// discord won't take more attachments than this on one message.
+pub const MAX_ATTACHMENTS: usize = 10;
+
+/// a file collected from the workspace after a job.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Artifact {
+    /// the job that produced it.
+    pub job: PipelineName,
+    /// relative to the repo root.
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Artifact {
+    /// where the artifact is stored, relative to its run's dir.
+    pub fn stored_path(&self) -> PathBuf {
+        Path::new(&self.job).join(&self.path)
+    }
+}
+
+/// where a run's artifacts are stored.
+pub fn run_dir(run_id: RunId) -> PathBuf {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(ARTIFACTS_DIR);
+    path.push(run_id.to_string());
+
+    path
+}
+
+/// copies `src` to `dst`, returning the sha256 of the contents.
+fn copy_hashed(src: &Path, dst: &Path) -> Result<String> {
+    let mut src = File::open(src)?;
+    let mut dst = File::create(dst)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = src.read(&mut buf)?;
+
+        if n == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..n]);
+        dst.write_all(&buf[..n])?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// every file matched by `pattern`, descending into matched directories.
+fn matches(workspace: &Path, pattern: &Path) -> Result<Vec<PathBuf>> {
+    if pattern.is_absolute()
+        || pattern
+            .components()
+            .any(|part| part == Component::ParentDir)
+    {
+        bail!("artifact {pattern:?} is outside of the repo");
+    }
+
+    let mut files = Vec::new();
+
+    for path in glob(&workspace.join(pattern).to_string_lossy())? {
+        let path = path?;
+
+        if path.is_dir() {
+            for inner in glob(&path.join("**/*").to_string_lossy())? {
+                let inner = inner?;
+
+                if inner.is_file() {
+                    files.push(inner);
+                }
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// copies the files matching `patterns` out of the workspace and stores them, with their
+/// checksums, under the run id and job. the patterns that matched no files are returned as well,
+/// so they can be pointed out.
+pub fn collect<'a>(
+    run_id: RunId,
+    job: &str,
+    workspace: &Path,
+    patterns: &'a [PathBuf],
+) -> Result<(Vec<Artifact>, Vec<&'a Path>)> {
+    let dir = run_dir(run_id);
+    let mut artifacts: Vec<Artifact> = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for pattern in patterns {
+        let files = matches(workspace, pattern)?;
+
+        if files.is_empty() {
+            unmatched.push(pattern.as_path());
+        }
+
+        for file in files {
+            let path = file.strip_prefix(workspace)?.to_path_buf();
+
+            if artifacts.iter().any(|artifact| artifact.path == path) {
+                continue;
+            }
+
+            let dst = dir.join(job).join(&path);
+
+            if let Some(parent) = dst.parent() {
+                create_dir_all(parent)?;
+            }
+
+            artifacts.push(Artifact {
+                job: job.to_string(),
+                sha256: copy_hashed(&file, &dst)?,
+                size: file.metadata()?.len(),
+                path,
+            });
+        }
+    }
+
+    add_to_manifest(run_id, job, &artifacts)?;
+
+    Ok((artifacts, unmatched))
+}
+
+/// replaces a jobs entries in a runs manifest. other jobs of the run may have already stored
+/// theirs.
+fn add_to_manifest(run_id: RunId, job: &str, artifacts: &[Artifact]) -> Result<()> {
+    let dir = run_dir(run_id);
+    let mut manifest = list(run_id).unwrap_or_default();
+    manifest.retain(|artifact| artifact.job != job);
+    manifest.extend(artifacts.iter().cloned());
+
+    create_dir_all(&dir)?;
+    write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+
+    Ok(())
+}
+
+/// copies the artifacts a job stored in an earlier run to a new one, for jobs a re-run skips.
+pub fn reuse(from: RunId, to: RunId, job: &str) -> Result<Vec<Artifact>> {
+    let artifacts: Vec<Artifact> = list(from)?
+        .into_iter()
+        .filter(|artifact| artifact.job == job)
+        .collect();
+
+    for artifact in artifacts.iter() {
+        let dst = run_dir(to).join(artifact.stored_path());
+
+        if let Some(parent) = dst.parent() {
+            create_dir_all(parent)?;
+        }
+
+        copy(run_dir(from).join(artifact.stored_path()), dst)?;
+    }
+
+    add_to_manifest(to, job, &artifacts)?;
+
+    Ok(artifacts)
+}
+
+/// copies the artifacts a job of the run produced into the workspace.
+pub fn restore(run_id: RunId, job: &str, workspace: &Path) -> Result<()> {
+    let artifacts: Vec<Artifact> = list(run_id)?
+        .into_iter()
+        .filter(|artifact| artifact.job == job)
+        .collect();
+
+    if artifacts.is_empty() {
+        bail!("{job} produced no artifacts");
+    }
+
+    for artifact in artifacts {
+        let dst = workspace.join(&artifact.path);
+
+        if let Some(parent) = dst.parent() {
+            create_dir_all(parent)?;
+        }
+
+        copy(run_dir(run_id).join(artifact.stored_path()), dst)?;
+    }
+
+    Ok(())
+}
+
+/// the artifacts stored for a run.
+pub fn list(run_id: RunId) -> Result<Vec<Artifact>> {
+    let manifest = run_dir(run_id).join(MANIFEST_FILE);
+
+    if !manifest.exists() {
+        bail!("run {run_id} has no artifacts");
+    }
+
+    Ok(serde_json::from_str(&read_to_string(manifest)?)?)
+}
+
+/// the stored file for one of a run's artifacts, by its stored path (`job/path`). only paths in
+/// the manifest are returned.
+pub fn find(run_id: RunId, path: &Path) -> Result<(Artifact, PathBuf)> {
+    let Some(artifact) = list(run_id)?
+        .into_iter()
+        .find(|artifact| artifact.stored_path() == path)
+    else {
+        bail!("run {run_id} has no artifact {path:?}");
+    };
+
+    let file = run_dir(run_id).join(artifact.stored_path());
+
+    Ok((artifact, file))
+}
+
+/// human readable size.
+pub fn fmt_size(size: u64) -> String {
+    match size {
+        0..1024 => format!("{size} B"),
+        1024..1_048_576 => format!("{:.1} KiB", size as f64 / 1024.0),
+        _ => format!("{:.1} MiB", size as f64 / 1_048_576.0),
+    }
+}
@@ -1,48 +1,639 @@
-use anyhow::{bail, Result};
-use discord_ci_cd::ci_cd::{Pipelines, PIPELINE_FILE};
-use std::fs::read_to_string;
-use std::process::Command;
-use std::{env, path::PathBuf};
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
+use discord_ci_cd::ci_cd::{
+    extend_env, interpolate, matrix_label, matrix_vars, read_pipeline_file, run_job,
+    workspace_head, workspace_tag, Env, FailureKind, Job, Outcome, Pipeline, PipelineName, Repo,
+    RunContext, RunEvent, Runners, IMAGE_VAR, PIPELINE_VAR, STATUS_VAR, STEPS_VAR,
+};
+use discord_ci_cd::events::{failure_cause, RunnerEvent, RunnerExit, Stream};
+use discord_ci_cd::expr::should_run;
+use discord_ci_cd::include::Includes;
+use discord_ci_cd::inputs::{self, input_var};
+use discord_ci_cd::lint::{self, Severity};
+use discord_ci_cd::outputs::{output_vars, parse_outputs, OUTPUT_VAR};
+use discord_ci_cd::services::RunServices;
+use discord_ci_cd::summary::SUMMARY_VAR;
+use docker_command::Launcher;
+use std::collections::HashMap;
+use std::fs::{read_to_string, remove_file, write};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{exit, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use url::Url;
 // use tokio::fs::read_to_string;
 
-// #[tokio::main]
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+/// runs dcicd pipelines, in ci or locally to debug them.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// runs a pipeline from a repos `.dcicd.toml`.
+    Run {
+        pipeline: PipelineName,
+        /// the repo to run in.
+        #[arg(long, default_value = ".")]
+        repo: PathBuf,
+        /// run in the runner image the backend would build. needs docker and the runner image
+        /// context installed like on the server.
+        #[arg(long, conflicts_with = "host")]
+        docker: bool,
+        /// run the steps directly on this machine. the default.
+        #[arg(long)]
+        host: bool,
+        /// print events instead of logs. this is what the backend reads.
+        #[arg(long)]
+        events: bool,
+    },
+    /// checks a pipeline file without running anything.
+    Lint {
+        /// the file to check. defaults to the repos pipeline file.
+        file: Option<PathBuf>,
+        #[arg(long, default_value = ".")]
+        repo: PathBuf,
+    },
+    /// prints the json schema of `.dcicd.toml`, for editors.
+    Schema,
+}
+
+/// prints events for the backend, or as logs for people.
+struct Emitter {
+    events: bool,
+}
+
+impl Emitter {
+    fn emit(&self, event: RunnerEvent) {
+        if self.events {
+            event.emit();
+        } else if let Some(text) = event.render() {
+            println!("{text}");
+        }
+    }
+}
 
-    let pipeline = if args.len() >= 2 {
-        args[1].clone()
-    } else {
-        bail!("missing pipeline argument");
+/// sends each line read from a steps stdout or stderr until it closes.
+fn forward(
+    pipe: impl Read + Send + 'static,
+    stream: Stream,
+    tx: Sender<(Stream, String)>,
+) -> JoinHandle<()> {
+    spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+
+        while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+            let text = String::from_utf8_lossy(&line)
+                .trim_end_matches(['\n', '\r'])
+                .to_string();
+
+            if tx.send((stream, text)).is_err() {
+                break;
+            }
+
+            line.clear();
+        }
+    })
+}
+
+/// runs a step, emitting its output as events. kills it (and anything it started) once it runs
+/// past `timeout`. returns whether it timed out.
+fn run_step(
+    emitter: &Emitter,
+    step: usize,
+    command: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<(ExitStatus, bool)> {
+    // its own process group so a timeout can kill the whole tree.
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut timed_out = false;
+    let (tx, rx) = channel();
+    let mut readers = Vec::new();
+
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(forward(stdout, Stream::Stdout, tx.clone()));
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(forward(stderr, Stream::Stderr, tx.clone()));
+    }
+
+    drop(tx);
+
+    // printed from here only so events never interleave.
+    loop {
+        match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok((stream, text)) => emitter.emit(RunnerEvent::Output { step, stream, text }),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if !timed_out && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Command::new("kill")
+                .args(["-KILL", "--", &format!("-{}", child.id())])
+                .status()
+                .ok();
+            timed_out = true;
+        }
+    }
+
+    for reader in readers {
+        reader.join().ok();
+    }
+
+    Ok((child.wait()?, timed_out))
+}
+
+/// the outputs a step wrote to its output file. lines that aren't an output are pointed out in its
+/// logs.
+fn read_outputs(emitter: &Emitter, step: usize, file: &Path) -> Env {
+    let (outputs, invalid) = parse_outputs(&read_to_string(file).unwrap_or_default());
+
+    for line in invalid {
+        emitter.emit(RunnerEvent::Output {
+            step,
+            stream: Stream::Stderr,
+            text: format!(
+                "ignoring `{line}` in ${OUTPUT_VAR}, expected `key=value` or `key<<DELIMITER` \
+                 closed by `DELIMITER`."
+            ),
+        });
+    }
+
+    outputs
+}
+
+fn main() {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        // --help and --version.
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            e.print().ok();
+            exit(RunnerExit::ConfigError.code());
+        }
+    };
+    let res = match cli.cmd {
+        Cmd::Run {
+            pipeline,
+            repo,
+            docker: true,
+            ..
+        } => run_docker(&pipeline, &repo),
+        Cmd::Run {
+            pipeline,
+            repo,
+            events,
+            ..
+        } => run_host(&pipeline, &repo, &Emitter { events }),
+        Cmd::Lint { file, repo } => run_lint(file, &repo),
+        Cmd::Schema => serde_json::to_string_pretty(&lint::schema())
+            .map(|schema| {
+                println!("{schema}");
+                RunnerExit::Passed
+            })
+            .map_err(|e| e.into()),
+    };
+    let exit_code = match res {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("{e}");
+            RunnerExit::ConfigError
+        }
     };
 
-    let mut pipeline_file: PathBuf = PathBuf::from("/home/dcicd-runner/repo/");
-    pipeline_file.push(PIPELINE_FILE);
-    let Ok(pipelines) =
-        toml::from_str::<Pipelines>(&read_to_string(pipeline_file).unwrap_or(String::new()))
-    else {
-        bail!("failed to read {PIPELINE_FILE}");
+    exit(exit_code.code());
+}
+
+/// prints every problem with a pipeline file like a compiler would.
+fn run_lint(file: Option<PathBuf>, repo: &Path) -> Result<RunnerExit> {
+    let (file, source) = match file {
+        Some(file) => {
+            let source = read_to_string(&file)
+                .map_err(|e| anyhow!("failed to read {}. {e}", file.display()))?;
+            (file, source)
+        }
+        None => {
+            let (file, source) = read_pipeline_file(repo)?;
+            (repo.join(file), source)
+        }
+    };
+    // includes are relative to the repo, wherever the file is.
+    let includes = Includes::from_dir(repo, HashMap::new());
+    let diagnostics = lint::lint(&file.to_string_lossy(), &source, &includes);
+
+    for diagnostic in diagnostics.iter() {
+        println!("{diagnostic}");
+    }
+
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        return Ok(RunnerExit::ConfigError);
+    }
+
+    if diagnostics.is_empty() {
+        println!("{} is valid", file.display());
+    }
+
+    Ok(RunnerExit::Passed)
+}
+
+fn load_pipeline(repo: &Path, pipeline: &str) -> Result<Pipeline> {
+    // in ci the backend already expanded it.
+    if let Ok(json) = env::var(PIPELINE_VAR) {
+        return serde_json::from_str(&json).map_err(|e| anyhow!("invalid {PIPELINE_VAR}. {e}"));
+    }
+
+    let (file, source) = read_pipeline_file(repo)?;
+
+    let pipelines = match lint::load(file, &source, &Includes::from_dir(repo, HashMap::new())) {
+        Ok((pipelines, warnings)) => {
+            for warning in warnings {
+                eprintln!("{warning}");
+            }
+
+            pipelines
+        }
+        Err(diagnostics) => bail!("{file} is invalid:\n{}", lint::report(&diagnostics)),
     };
 
     // find pipline
-    let Some(pipeline) = pipelines.get(&pipeline).map(|pl| pl.to_owned()) else {
+    let Some(pipeline) = pipelines.get(pipeline).map(|pl| pl.to_owned()) else {
         bail!("unknown pipeline: {pipeline}");
     };
 
-    for cmd in pipeline.script {
-        println!("$> {cmd}");
-        let status = Command::new("sh").args(["-c", &cmd]).status()?;
+    Ok(pipeline)
+}
 
-        if !status.success() {
-            let Some(code) = status.code() else {
-                println!("command '{cmd}', was cancled by a signal.");
+/// what the backend would run the pipeline with, for runs outside of ci. inputs are read from
+/// `DCICD_INPUT_<NAME>`, like steps get them.
+fn local_context(repo: &Path, pipeline_name: &str, pipeline: &Pipeline) -> Result<RunContext> {
+    let repo = repo.canonicalize()?;
+    let given: Env = pipeline
+        .inputs
+        .iter()
+        .flatten()
+        .filter_map(|(name, _)| Some((name.clone(), env::var(input_var(name)).ok()?)))
+        .collect();
+    let inputs = inputs::resolve(pipeline.inputs.as_ref(), &given)?;
+    let (commit_sha, branch) = workspace_head(&repo).unwrap_or(("unknown".into(), None));
+    let Ok(url) = Url::from_file_path(&repo) else {
+        bail!("invalid repo path {repo:?}");
+    };
 
-                break;
-            };
-            println!("command '{cmd}', exited with none-zero status '{code}'.");
+    Ok(RunContext {
+        // the pid keeps the service network apart from other local runs.
+        run_id: std::process::id().into(),
+        repo: Repo {
+            repo_name: repo
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            url,
+        },
+        commit_sha,
+        branch: branch.unwrap_or("HEAD".into()),
+        tag: workspace_tag(&repo),
+        pipeline_name: pipeline_name.to_string(),
+        triggered_by: env::var("USER").unwrap_or("local".into()),
+        event: RunEvent::Manual,
+        guild_id: None,
+        rerun_of: None,
+        channel_id: None,
+        inputs,
+        outputs: Env::new(),
+    })
+}
+
+/// runs the pipelines steps on this machine. the exit code tells the backend how the first
+/// failing step failed.
+fn run_host(pipeline_name: &str, repo: &Path, emitter: &Emitter) -> Result<RunnerExit> {
+    let pipeline = load_pipeline(repo, pipeline_name)?;
+
+    if pipeline.approval.is_some() {
+        println!("{pipeline_name} is an approval job, it has no steps to run.");
+        return Ok(RunnerExit::Passed);
+    }
+
+    // the backend always sets the built-in variables.
+    let in_ci = env::var("DCICD_RUN_ID").is_ok();
+
+    // the env (which holds the built-in variables), the matrix combination being run, then the
+    // pipelines env.
+    let mut vars: Env = env::vars().collect();
+    vars.remove(PIPELINE_VAR);
 
+    if !in_ci {
+        vars.extend(local_context(repo, pipeline_name, &pipeline)?.vars());
+    }
+
+    // the backend runs each matrix combination in its own container, locally they run one after
+    // another.
+    let cells = match (env::var("DCICD_MATRIX"), &pipeline.matrix) {
+        (Ok(cell), _) => vec![serde_json::from_str(&cell)?],
+        (Err(_), Some(matrix)) if !in_ci => matrix.cells(),
+        _ => vec![Env::new()],
+    };
+    let fail_fast = pipeline
+        .matrix
+        .as_ref()
+        .and_then(|matrix| matrix.fail_fast)
+        .unwrap_or(true);
+    let mut res = RunnerExit::Passed;
+
+    for cell in cells {
+        if !in_ci && pipeline.matrix.is_some() {
+            println!("=== matrix: {}", matrix_label(&cell));
+        }
+
+        let mut vars = vars.clone();
+        vars.extend(matrix_vars(&cell));
+
+        if let Some(env) = &pipeline.env {
+            extend_env(&mut vars, env);
+        }
+
+        // the backend checks the pipelines `if` before starting the runner.
+        if !in_ci && !should_run(pipeline.condition.as_deref(), &vars, false)? {
+            println!("=== skipped, `if` is false");
+            continue;
+        }
+
+        let cell_res = run_steps(&pipeline, &vars, repo, emitter)?;
+
+        if res == RunnerExit::Passed {
+            res = cell_res;
+        }
+
+        if res != RunnerExit::Passed && fail_fast {
             break;
         }
     }
 
-    Ok(())
+    Ok(res)
+}
+
+fn run_steps(
+    pipeline: &Pipeline,
+    vars: &Env,
+    repo: &Path,
+    emitter: &Emitter,
+) -> Result<RunnerExit> {
+    // the backend runs steps with a different image in their own container.
+    let (first, last) = match env::var(STEPS_VAR) {
+        Ok(steps) => match steps
+            .split_once('-')
+            .and_then(|(first, last)| Some((first.parse().ok()?, last.parse().ok()?)))
+        {
+            Some(steps) => steps,
+            None => bail!("invalid {STEPS_VAR}: {steps}"),
+        },
+        Err(_) => (1, pipeline.steps().len()),
+    };
+    let image = env::var(IMAGE_VAR).ok();
+    // a step failed in an earlier container.
+    let failed_before = env::var(STATUS_VAR).is_ok_and(|status| status == "failure");
+    // the exit of the first failing step. steps after it only run if their `if` says so.
+    let mut failure = None;
+    // what earlier steps wrote to `$DCICD_OUTPUT`.
+    let mut outputs = Env::new();
+
+    for (i, step) in pipeline.steps().into_iter().enumerate() {
+        if i + 1 < first || i + 1 > last {
+            continue;
+        }
+
+        let mut step_vars = vars.clone();
+        step_vars.extend(output_vars(&outputs));
+
+        if let Some(env) = &step.env {
+            extend_env(&mut step_vars, env);
+        }
+
+        let output_file =
+            env::temp_dir().join(format!("dcicd-output-{}-{}", std::process::id(), i + 1));
+        step_vars.insert(OUTPUT_VAR.into(), output_file.display().to_string());
+        let summary_file =
+            env::temp_dir().join(format!("dcicd-summary-{}-{}", std::process::id(), i + 1));
+        step_vars.insert(SUMMARY_VAR.into(), summary_file.display().to_string());
+
+        let name = interpolate(step.name(), &step_vars);
+
+        let failed = failed_before || failure.is_some();
+
+        match should_run(step.condition.as_deref(), &step_vars, failed) {
+            Ok(true) => {}
+            Ok(false) => {
+                emitter.emit(RunnerEvent::StepSkipped { step: i + 1, name });
+                continue;
+            }
+            Err(e) => {
+                eprintln!("step '{name}' has an invalid `if`. {e}");
+                return Ok(RunnerExit::ConfigError);
+            }
+        }
+
+        let cmd = interpolate(&step.run, &step_vars);
+        let (shell, flag) = step.shell.unwrap_or_default().program();
+        let mut command = Command::new(shell);
+        command
+            .args([flag, &cmd])
+            .env_clear()
+            .envs(&step_vars)
+            .current_dir(repo.join(step.working_directory.as_deref().unwrap_or(Path::new("."))));
+
+        let continue_on_error = step.continue_on_error.unwrap_or(false);
+        emitter.emit(RunnerEvent::StepStarted {
+            step: i + 1,
+            name: name.clone(),
+            image: image.clone(),
+            command: cmd,
+        });
+
+        let retry = step.retry.as_ref();
+        let mut attempt = 1;
+
+        let (status, timed_out, step_outputs) = loop {
+            let started = Instant::now();
+            let timeout = step.timeout.map(Duration::from_secs);
+
+            // every attempt starts with an empty file.
+            if let Err(e) = write(&output_file, "") {
+                eprintln!("failed to create ${OUTPUT_VAR} for step '{name}'. {e}");
+                return Ok(RunnerExit::Error);
+            }
+
+            if let Err(e) = write(&summary_file, "") {
+                eprintln!("failed to create ${SUMMARY_VAR} for step '{name}'. {e}");
+                return Ok(RunnerExit::Error);
+            }
+
+            let reason = match run_step(emitter, i + 1, &mut command, timeout) {
+                Ok((status, timed_out)) => {
+                    let duration_ms = started.elapsed().as_millis() as u64;
+                    let step_outputs = read_outputs(emitter, i + 1, &output_file);
+                    let markdown = read_to_string(&summary_file)
+                        .ok()
+                        .filter(|markdown| !markdown.trim().is_empty());
+                    emitter.emit(RunnerEvent::StepFinished {
+                        step: i + 1,
+                        name: name.clone(),
+                        exit_code: status.code(),
+                        signal: status.signal(),
+                        duration_ms,
+                        continue_on_error,
+                        timed_out,
+                        outputs: step_outputs.clone(),
+                        markdown,
+                    });
+
+                    let kind = if timed_out {
+                        FailureKind::Timeout
+                    } else {
+                        FailureKind::ExitCode
+                    };
+
+                    if status.success()
+                        || !retry
+                            .is_some_and(|retry| retry.should_retry(attempt, kind, status.code()))
+                    {
+                        break (status, timed_out, step_outputs);
+                    }
+
+                    failure_cause(status.code(), status.signal(), timed_out, duration_ms)
+                }
+                Err(e) => {
+                    if !retry
+                        .is_some_and(|retry| retry.should_retry(attempt, FailureKind::Error, None))
+                    {
+                        eprintln!("failed to run step '{name}'. {e}");
+                        remove_file(&output_file).ok();
+                        remove_file(&summary_file).ok();
+                        return Ok(RunnerExit::Error);
+                    }
+
+                    format!("failed to start, {e}")
+                }
+            };
+
+            let delay = retry.map(|retry| retry.delay(attempt)).unwrap_or_default();
+            attempt += 1;
+            emitter.emit(RunnerEvent::StepRetry {
+                step: i + 1,
+                name: name.clone(),
+                attempt,
+                max_attempts: retry.map(|retry| retry.max_attempts).unwrap_or(attempt),
+                delay_ms: delay.as_millis() as u64,
+                reason,
+            });
+            sleep(delay);
+        };
+
+        remove_file(&output_file).ok();
+        remove_file(&summary_file).ok();
+        outputs.extend(step_outputs);
+
+        if status.success() || continue_on_error || failure.is_some() {
+            continue;
+        }
+
+        failure = Some(if timed_out {
+            RunnerExit::Timeout
+        } else if status.signal().is_some() {
+            RunnerExit::Signal
+        } else {
+            RunnerExit::StepFailed
+        });
+    }
+
+    Ok(failure.unwrap_or(RunnerExit::Passed))
+}
+
+/// runs the pipeline in runner containers, like the backend does. secrets are taken from the
+/// local env.
+fn run_docker(pipeline_name: &str, repo: &Path) -> Result<RunnerExit> {
+    let repo = repo.canonicalize()?;
+    let pipeline = load_pipeline(&repo, pipeline_name)?;
+
+    if pipeline.approval.is_some() {
+        println!("{pipeline_name} is an approval job, it has no steps to run.");
+        return Ok(RunnerExit::Passed);
+    }
+
+    let ctx = local_context(&repo, pipeline_name, &pipeline)?;
+    let Some(launcher) = Launcher::auto() else {
+        bail!("--docker needs docker or podman");
+    };
+
+    if let Some(producers) = &pipeline.uses_artifacts {
+        eprintln!(
+            "artifacts of {} are not restored when running locally.",
+            producers.join(", ")
+        );
+    }
+
+    let mut secrets = Env::new();
+
+    for name in pipeline.secrets.iter().flatten() {
+        match env::var(name) {
+            Ok(val) => {
+                secrets.insert(name.clone(), val);
+            }
+            Err(_) => eprintln!("secret {name} is not set in the env."),
+        }
+    }
+
+    let job = Job {
+        name: pipeline_name.to_string(),
+        pipeline,
+        secrets,
+        previous: None,
+    };
+    let mut runners = Runners::new(launcher, repo, false);
+    let mut services = RunServices::new(ctx.run_id);
+
+    if let Some(job_services) = &job.pipeline.services {
+        if let Err(e) = services.start(&runners.launcher, &job.name, job_services) {
+            eprintln!("failed to start services. {e}");
+            services.teardown(&runners.launcher);
+            return Ok(RunnerExit::Error);
+        }
+    }
+
+    let mut output = String::new();
+    runners.network = services.network();
+    let results = run_job(&ctx, &job, &mut runners, &mut output, false);
+    services.teardown(&runners.launcher);
+    print!("{output}");
+
+    for cell in results.iter() {
+        if let Some(reason) = &cell.reason {
+            eprintln!("{reason}");
+        }
+    }
+
+    Ok(
+        match Outcome::combine(results.iter().map(|cell| cell.outcome)) {
+            Outcome::Passed => RunnerExit::Passed,
+            Outcome::Error => RunnerExit::Error,
+            _ => RunnerExit::StepFailed,
+        },
+    )
 }
@@ -1,12 +1,20 @@
-use actix_web::{post, web, Result};
-use discord_ci_cd::ci_cd::{PipelineName, Repo};
+use actix_web::{get, http::header, post, web, App, HttpResponse, HttpServer, Result};
+use discord_ci_cd::{
+    artifacts,
+    ci_cd::{PipelineName, Repo, RunId},
+};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, env, path::PathBuf};
 use url::Url;
 
 type UserId = u64;
 type AuthToken = String;
 
+/// env var holding the address to listen on.
+const ADDR_VAR: &str = "DCICD_HTTP_ADDR";
+const DEFAULT_ADDR: &str = "0.0.0.0:8080";
+
+#[allow(dead_code)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 enum PipelineState {
     /// a pipeline is running
@@ -40,8 +48,8 @@ struct RegisterArgs {
 
 /// end point to register a new git repo
 #[post("/register")]
-async fn register(data: web::Data<AppState>, body: web::Json<RegisterArgs>) -> Result<String> {
-    let auth_token = body.auth.clone();
+async fn register(_data: web::Data<AppState>, body: web::Json<RegisterArgs>) -> Result<String> {
+    let _auth_token = body.auth.clone();
 
     // TODO: write verify_auth_token and use it to verify an
     // verify_auth_token(auth_token)?;
@@ -51,7 +59,46 @@ async fn register(data: web::Data<AppState>, body: web::Json<RegisterArgs>) -> R
     Ok(format!("git repo \"{clone_url}\" registered successfully"))
 }
 
+/// end point to download a runs artifact
+#[get("/artifacts/{run_id}/{path:.*}")]
+async fn artifact(path: web::Path<(RunId, PathBuf)>) -> HttpResponse {
+    let (run_id, path) = path.into_inner();
+
+    let Ok((artifact, file)) = artifacts::find(run_id, &path) else {
+        return HttpResponse::NotFound().body(format!("run {run_id} has no artifact {path:?}"));
+    };
+
+    match tokio::fs::read(file).await {
+        Ok(contents) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"",
+                    artifact
+                        .path
+                        .file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default()
+                ),
+            ))
+            .insert_header(("X-Checksum-Sha256", artifact.sha256))
+            .body(contents),
+        Err(e) => HttpResponse::InternalServerError().body(format!("failed to read artifact. {e}")),
+    }
+}
+
 #[actix::main]
-async fn main() -> Result<()> {
-    Ok(())
+async fn main() -> std::io::Result<()> {
+    let addr = env::var(ADDR_VAR).unwrap_or(DEFAULT_ADDR.into());
+
+    HttpServer::new(|| {
+        App::new()
+            .app_data(web::Data::new(AppState::default()))
+            .service(register)
+            .service(artifact)
+    })
+    .bind(addr)?
+    .run()
+    .await
 }
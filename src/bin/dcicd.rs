@@ -1,7 +1,11 @@
 use crossbeam::channel::unbounded;
 use discord_ci_cd::{
+    artifacts, cache,
     ci_cd::{run_backend, Backend},
-    load, resgister, run, show, Data,
+    environments, event_handler, history, load, pipeline, poll,
+    poll::{run_poller, PollState},
+    protect_environment, rebuild_runner, rerun, resgister, rollback, run, run_approvals, secret,
+    service_logs, show, validate, Data,
 };
 use poise::serenity_prelude::{self as serenity, futures::lock::Mutex};
 use std::collections::HashMap;
@@ -16,24 +20,58 @@ async fn main() {
     let intents = serenity::GatewayIntents::non_privileged();
     let (cmd_tx, cmd_rx) = unbounded();
     let (log_tx, log_rx) = unbounded();
-    let backend = Arc::new(Mutex::new(Backend::new(log_tx)));
+    let (approval_tx, approval_rx) = unbounded();
+    let poll_state = PollState::load();
+    let git_links = poll_state.urls();
+    let mut backend = Backend::new(log_tx);
+    backend.repos = git_links.clone();
+    backend.approvals = Some(approval_tx);
+    let backend = Arc::new(Mutex::new(backend));
+    let poller = Arc::new(Mutex::new(poll_state));
+    let approvals = Arc::new(Mutex::new(HashMap::new()));
     let data = Data {
-        git_links: HashMap::default(),
+        git_links,
         backend: backend.clone(),
-        send_cmd: cmd_tx,
+        send_cmd: cmd_tx.clone(),
         get_output: log_rx,
+        poller: poller.clone(),
+        approvals: approvals.clone(),
     };
 
     spawn(run_backend(cmd_rx, backend));
+    spawn(run_poller(poller, cmd_tx));
 
     let framework = poise::Framework::builder()
         .options(poise::FrameworkOptions {
-            commands: vec![resgister(), show(), load(), run()],
+            commands: vec![
+                resgister(),
+                show(),
+                load(),
+                run(),
+                rerun(),
+                poll(),
+                secret(),
+                artifacts(),
+                cache(),
+                rebuild_runner(),
+                service_logs(),
+                history(),
+                environments(),
+                protect_environment(),
+                rollback(),
+                validate(),
+                pipeline(),
+            ],
+            event_handler: |ctx, event, framework, data| {
+                Box::pin(event_handler(ctx, event, framework, data))
+            },
             ..Default::default()
         })
         .setup(|ctx, _ready, framework| {
             Box::pin(async move {
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                // approvals are posted outside of any command.
+                spawn(run_approvals(approval_rx, ctx.http.clone(), approvals));
                 Ok(Arc::new(Mutex::new(data)))
             })
         })
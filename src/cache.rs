@@ -0,0 +1,242 @@
+use crate::ci_cd::{interpolate, Env, RepoName, STATE_DIR};
+use anyhow::{bail, Result};
+use docker_command::command_run::Command;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, read, read_to_string, remove_file, write},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const CACHES_DIR: &str = "caches";
+pub const CACHE_INDEX_FILE: &str = "index.json";
+/// env var to override the total size caches may use, in bytes.
+pub const CACHE_LIMIT_VAR: &str = "DCICD_CACHE_LIMIT";
+pub const DEFAULT_CACHE_LIMIT: u64 = 10 * 1024 * 1024 * 1024;
+
+/// a saved cache tarball.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub repo: RepoName,
+    pub key: String,
+    pub size: u64,
+    /// unix time the entry was last saved or restored.
+    pub last_used: u64,
+}
+
+/// every saved cache. the least recently used are evicted once they take up too much space.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    entries: Vec<CacheEntry>,
+}
+
+fn caches_dir() -> PathBuf {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(CACHES_DIR);
+
+    path
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+/// keeps cache file names to characters that are safe in a path.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+impl CacheEntry {
+    fn path(&self) -> PathBuf {
+        tarball(&self.repo, &self.key)
+    }
+}
+
+fn tarball(repo: &str, key: &str) -> PathBuf {
+    caches_dir()
+        .join(sanitize(repo))
+        .join(format!("{}.tar.gz", sanitize(key)))
+}
+
+impl CacheIndex {
+    fn load() -> Self {
+        read_to_string(caches_dir().join(CACHE_INDEX_FILE))
+            .ok()
+            .and_then(|index| serde_json::from_str(&index).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        create_dir_all(caches_dir())?;
+        write(
+            caches_dir().join(CACHE_INDEX_FILE),
+            serde_json::to_string_pretty(self)?,
+        )?;
+
+        Ok(())
+    }
+
+    /// drops the least recently used entries until they all fit in the size limit. returns the
+    /// dropped ones.
+    fn evict(&mut self) -> Vec<CacheEntry> {
+        let limit = std::env::var(CACHE_LIMIT_VAR)
+            .ok()
+            .and_then(|limit| limit.parse().ok())
+            .unwrap_or(DEFAULT_CACHE_LIMIT);
+        self.entries.sort_by_key(|entry| entry.last_used);
+        let mut evicted = Vec::new();
+
+        while self.entries.iter().map(|entry| entry.size).sum::<u64>() > limit {
+            let entry = self.entries.remove(0);
+            remove_file(entry.path()).ok();
+            evicted.push(entry);
+        }
+
+        evicted
+    }
+}
+
+/// the short sha256 of a file in the workspace, or "none" if it doesn't exist.
+fn hash_file(workspace: &Path, file: &str) -> String {
+    match read(workspace.join(file)) {
+        Ok(contents) => format!("{:x}", Sha256::digest(contents))[..16].to_string(),
+        Err(_) => "none".into(),
+    }
+}
+
+/// expands a cache key template. `${hash:FILE}` becomes the hash of a file in the workspace and
+/// other `${VAR}`s are interpolated from `vars`.
+pub fn resolve_key(template: &str, vars: &Env, workspace: &Path) -> String {
+    let mut key = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${hash:") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+
+        key.push_str(&rest[..start]);
+        key.push_str(&hash_file(workspace, &rest[start + 7..start + len]));
+        rest = &rest[start + len + 1..];
+    }
+
+    key.push_str(rest);
+    interpolate(&key, vars)
+}
+
+/// unpacks a saved cache into the workspace. returns false on a cache miss.
+pub fn restore(repo: &str, key: &str, workspace: &Path) -> Result<bool> {
+    let mut index = CacheIndex::load();
+    let Some(entry) = index
+        .entries
+        .iter_mut()
+        .find(|entry| entry.repo == repo && entry.key == key)
+    else {
+        return Ok(false);
+    };
+
+    let mut tar = Command::new("tar");
+    tar.add_arg_pair("-xzf", entry.path())
+        .add_arg_pair("-C", workspace);
+    tar.run()?;
+
+    entry.last_used = now();
+    index.save()?;
+
+    Ok(true)
+}
+
+/// packs `paths` (relative to the workspace) into a cache under `key`, evicting old caches if
+/// they're now over the size limit. returns the size of the new cache and the evicted ones.
+pub fn save(
+    repo: &str,
+    key: &str,
+    workspace: &Path,
+    paths: &[PathBuf],
+) -> Result<(u64, Vec<CacheEntry>)> {
+    let mut existing = Vec::new();
+
+    for path in paths {
+        if path.is_absolute() || path.components().any(|part| part == Component::ParentDir) {
+            bail!("cache path {path:?} is outside of the repo");
+        }
+
+        if workspace.join(path).exists() {
+            existing.push(path.as_os_str());
+        }
+    }
+
+    if existing.is_empty() {
+        bail!("none of the cache paths exist");
+    }
+
+    let file = tarball(repo, key);
+
+    if let Some(parent) = file.parent() {
+        create_dir_all(parent)?;
+    }
+
+    let mut tar = Command::new("tar");
+    tar.add_arg_pair("-czf", &file)
+        .add_arg_pair("-C", workspace)
+        .add_args(existing);
+    tar.run()?;
+
+    let size = file.metadata()?.len();
+    let mut index = CacheIndex::load();
+    index
+        .entries
+        .retain(|entry| !(entry.repo == repo && entry.key == key));
+    index.entries.push(CacheEntry {
+        repo: repo.to_string(),
+        key: key.to_string(),
+        size,
+        last_used: now(),
+    });
+    let evicted = index.evict();
+    index.save()?;
+
+    Ok((size, evicted))
+}
+
+/// the caches saved for a repo, most recently used first.
+pub fn list(repo: &str) -> Vec<CacheEntry> {
+    let mut entries: Vec<CacheEntry> = CacheIndex::load()
+        .entries
+        .into_iter()
+        .filter(|entry| entry.repo == repo)
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+
+    entries
+}
+
+/// deletes every cache of a repo. returns how many there were.
+pub fn clear(repo: &str) -> Result<usize> {
+    let mut index = CacheIndex::load();
+    let (cleared, kept): (Vec<_>, Vec<_>) = index
+        .entries
+        .into_iter()
+        .partition(|entry| entry.repo == repo);
+
+    for entry in cleared.iter() {
+        remove_file(entry.path()).ok();
+    }
+
+    index.entries = kept;
+    index.save()?;
+
+    Ok(cleared.len())
+}
@@ -1,19 +1,41 @@
-use anyhow::{bail, Result};
+use crate::{
+    approval::{self, Approval, ApprovalRecord, ApprovalRequest},
+    artifacts::{self, ATTACH_LIMIT, MAX_ATTACHMENTS},
+    cache,
+    environments::{self, Deployment, EnvironmentName, Environments},
+    events::{fmt_duration, parse_output, RunnerExit, StepResult},
+    expr::{should_run, Expr},
+    history::{self, CellRecord, JobRecord, RunRecord},
+    include::{Include, Includes},
+    inputs::{self, input_var, Inputs},
+    lint,
+    outputs::{fmt_outputs, output_vars},
+    secrets::{mask, GuildId, SecretStore},
+    services::{service_vars, RunServices, Service, ServiceName},
+    summary,
+};
+use anyhow::{anyhow, bail, Result};
 use crossbeam::channel::{Receiver, Sender};
 use docker_command::{command_run::Command, BuildOpt, Launcher, RunOpt, Volume};
-use git2::Repository;
+use git2::{Repository, ResetType, Status, StatusOptions};
 use poise::serenity_prelude::futures::lock::Mutex;
+use schemars::{JsonSchema, Schema, SchemaGenerator};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    fs::{create_dir_all, read_to_string, write},
+    panic::resume_unwind,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    fs::{read_to_string, remove_dir_all},
+    fs::remove_dir_all,
     spawn,
-    task::JoinHandle,
+    task::{spawn_blocking, JoinHandle},
     time::sleep,
 };
 use url::Url;
@@ -21,9 +43,37 @@ use url::Url;
 pub type PipelineName = String;
 pub type RepoName = String;
 pub type Pipelines = HashMap<PipelineName, Pipeline>;
+pub type RunId = u64;
+pub type Env = BTreeMap<String, String>;
+pub type OnComplete = Box<dyn Fn(RunReport) + Send + Sync>;
+/// a discord channel id.
+pub type ChannelId = u64;
 
-pub const CACHE_DIR: &str = &"/tmp/dcicd/";
-pub const PIPELINE_FILE: &str = &".dcicd.toml";
+pub const CACHE_DIR: &str = "/tmp/dcicd/";
+/// where the workspace is mounted in runner containers.
+pub const RUNNER_REPO_DIR: &str = "/home/dcicd-runner/repo/";
+/// the names a pipeline file can have. a repo may only have one of them.
+pub const PIPELINE_FILES: [&str; 4] = [".dcicd.toml", ".dcicd.yaml", ".dcicd.yml", ".dcicd.json"];
+pub const STATE_DIR: &str = "/var/lib/dcicd/";
+pub const RUN_ID_FILE: &str = "last_run_id";
+/// where the runner dockerfile lives.
+pub const RUNNER_CONTEXT: &str = "/etc/dcicd/docker/";
+/// the image holding the `dcicd-runner` binary, built from `Dockerfile.mk-runner`.
+pub const RUNNER_BIN_IMAGE: &str = "dcicd-runner:latest";
+/// runner images are tagged `dcicd:<hash of what went into them>`.
+pub const RUNNER_IMAGE: &str = "dcicd";
+/// tells the runner which steps to run, as `FIRST-LAST` (numbered from 1).
+pub const STEPS_VAR: &str = "DCICD_STEPS";
+/// the image the runner was started in, for its step headers.
+pub const IMAGE_VAR: &str = "DCICD_IMAGE";
+/// the pipeline to run as json, expanded by the backend so the runner doesn't resolve includes.
+pub const PIPELINE_VAR: &str = "DCICD_PIPELINE_JSON";
+/// `failure` if a step of the job failed in an earlier container, for the runners `if`s.
+pub const STATUS_VAR: &str = "DCICD_JOB_STATUS";
+/// prefix of the built-in variables. pipelines can't override these.
+pub const BUILTIN_VAR_PREFIX: &str = "DCICD_";
+/// the longest a retry waits, however far its backoff has doubled.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Repo {
@@ -31,13 +81,749 @@ pub struct Repo {
     pub url: Url,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Pipeline {
     // pub name: PipelineName,
+    /// required unless the pipeline is an `approval` job.
+    #[serde(default)]
     pub container: String,
-    pub script: Vec<String>,
+    /// required unless the pipeline is an `approval` job.
+    #[serde(default)]
+    pub script: Vec<Step>,
     // pub script_loc: usize,
     pub artifacts: Option<Vec<PathBuf>>,
+    /// environment variables for every step.
+    pub env: Option<Env>,
+    /// names of the repo/guild secrets this pipeline is given as environment variables.
+    pub secrets: Option<Vec<String>>,
+    /// branches that trigger this pipeline when new commits are found by the poller.
+    pub branches: Option<Vec<String>>,
+    /// runs the pipeline once per combination of these variables.
+    pub matrix: Option<Matrix>,
+    /// pipelines whose artifacts are copied into the workspace first. they run as earlier jobs of
+    /// the same run.
+    pub uses_artifacts: Option<Vec<PipelineName>>,
+    /// paths kept between runs.
+    pub cache: Option<CacheConfig>,
+    /// containers started next to the job, reachable by their name.
+    pub services: Option<BTreeMap<ServiceName, Service>>,
+    /// a template or pipeline this one inherits from. keys set here win, `env` is merged.
+    pub extends: Option<String>,
+    /// only run the pipeline if this expression is true, e.g. `branch == 'main'`.
+    #[serde(rename = "if")]
+    pub condition: Option<String>,
+    /// steps run after `script` if a step failed.
+    pub on_failure: Option<Vec<Step>>,
+    /// steps run last whether or not a step failed, e.g. cleanup.
+    pub always: Option<Vec<Step>>,
+    /// runs a failed matrix combination (or the whole pipeline without a matrix) again.
+    pub retry: Option<Retry>,
+    /// pipelines that run as earlier jobs of the same run, without using their artifacts.
+    pub needs: Option<Vec<PipelineName>>,
+    /// makes this an approval job, which runs no steps and waits for someone to approve the run.
+    pub approval: Option<Approval>,
+    /// the environment this pipeline deploys to, e.g. `production`. passed runs are recorded as
+    /// its current deployment, and its protection rules apply before the pipeline runs.
+    pub environment: Option<EnvironmentName>,
+    /// values asked for when the pipeline is run from discord, e.g. a version to release.
+    pub inputs: Option<Inputs>,
+}
+
+impl Pipeline {
+    /// every step in the order they run. `on_failure` and `always` steps come after `script`, with
+    /// `failure()` or `always()` added to their `if`.
+    pub fn steps(&self) -> Vec<StepConfig> {
+        let mut steps: Vec<StepConfig> = self.script.iter().map(Step::config).collect();
+
+        for (cleanup, status) in [(&self.on_failure, "failure()"), (&self.always, "always()")] {
+            for step in cleanup.iter().flatten() {
+                let mut step = step.config();
+                step.condition = Some(match step.condition {
+                    Some(condition) => format!("{status} && ({condition})"),
+                    None => status.to_string(),
+                });
+                steps.push(step);
+            }
+        }
+
+        steps
+    }
+}
+
+/// the whole pipeline file. only used for its schema, the pipelines are read once includes and
+/// `extends` are resolved.
+#[derive(JsonSchema)]
+pub struct PipelineFile {
+    /// files to add the templates and pipelines of.
+    pub include: Option<Vec<Include>>,
+    /// partial pipelines, only run through pipelines that extend them.
+    pub templates: Option<BTreeMap<String, Template>>,
+    #[schemars(flatten)]
+    pub pipelines: Pipelines,
+}
+
+/// a pipeline that's only inherited from, so nothing in it is required.
+pub struct Template;
+
+impl JsonSchema for Template {
+    fn schema_name() -> Cow<'static, str> {
+        "Template".into()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        let mut schema = Pipeline::json_schema(generator);
+        schema.remove("required");
+
+        schema
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// e.g. `cargo-${hash:Cargo.lock}`. `${hash:FILE}` is the hash of a file in the repo, other
+    /// variables are interpolated like in steps.
+    pub key: String,
+    /// relative to the repo root.
+    pub paths: Vec<PathBuf>,
+}
+
+/// one pipeline of a run. a run is the requested pipeline plus the jobs it uses artifacts from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Job {
+    pub name: PipelineName,
+    pub pipeline: Pipeline,
+    /// the decrypted secrets the pipeline asked for.
+    pub secrets: Env,
+    /// the job in the run being re-run, for failed only re-runs. passed jobs and matrix
+    /// combinations aren't run again.
+    pub previous: Option<(RunId, JobRecord)>,
+}
+
+/// which of `PIPELINE_FILES` a repo has, going by `exists`. having more than one is an error so
+/// pipelines are never read from the wrong one.
+pub fn pipeline_file(exists: impl Fn(&str) -> bool) -> Result<&'static str> {
+    let found: Vec<&str> = PIPELINE_FILES
+        .into_iter()
+        .filter(|file| exists(file))
+        .collect();
+
+    match found.as_slice() {
+        [] => bail!(
+            "no pipeline file, expected one of {}",
+            PIPELINE_FILES.join(", ")
+        ),
+        [file] => Ok(file),
+        files => bail!("found more than one pipeline file: {}", files.join(", ")),
+    }
+}
+
+/// the name and contents of the pipeline file in `dir`.
+pub fn read_pipeline_file(dir: &Path) -> Result<(&'static str, String)> {
+    let file = pipeline_file(|file| dir.join(file).is_file())?;
+
+    match read_to_string(dir.join(file)) {
+        Ok(source) => Ok((file, source)),
+        Err(e) => bail!("failed to read {file}. {e}"),
+    }
+}
+
+/// the pipelines to run, in order, for `target`. producers of artifacts come before the jobs that
+/// use them.
+pub fn job_order(pipelines: &Pipelines, target: &str) -> Result<Vec<PipelineName>> {
+    fn visit(
+        pipelines: &Pipelines,
+        name: &str,
+        visiting: &mut Vec<PipelineName>,
+        order: &mut Vec<PipelineName>,
+    ) -> Result<()> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+
+        if visiting.iter().any(|seen| seen == name) {
+            bail!("pipelines {} depend on each other", visiting.join(" -> "));
+        }
+
+        let Some(pipeline) = pipelines.get(name) else {
+            bail!("unknown pipeline: {name}");
+        };
+
+        visiting.push(name.to_string());
+
+        for producer in pipeline.uses_artifacts.iter().flatten() {
+            match pipelines.get(producer) {
+                Some(p) if p.artifacts.is_none() => {
+                    bail!("{name} uses artifacts from {producer}, which declares none")
+                }
+                _ => visit(pipelines, producer, visiting, order)?,
+            }
+        }
+
+        for needed in pipeline.needs.iter().flatten() {
+            visit(pipelines, needed, visiting, order)?;
+        }
+
+        visiting.pop();
+        order.push(name.to_string());
+
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    visit(pipelines, target, &mut Vec::new(), &mut order)?;
+
+    Ok(order)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+pub struct Matrix {
+    /// extra combinations to run.
+    pub include: Option<Vec<Env>>,
+    /// combinations to skip. an entry matches every combination that has all of its values.
+    pub exclude: Option<Vec<Env>>,
+    /// skip the remaining combinations after the first failure. defaults to true.
+    pub fail_fast: Option<bool>,
+    /// variable name -> the values to run with. available as `${matrix.NAME}`.
+    #[serde(flatten)]
+    pub vars: BTreeMap<String, Vec<String>>,
+}
+
+impl Matrix {
+    /// every combination to run, in order. `fail_fast` skips the ones after the first failure.
+    pub fn cells(&self) -> Vec<Env> {
+        // a matrix of only `include`s runs just those.
+        let only_includes = self.vars.is_empty()
+            && self
+                .include
+                .as_ref()
+                .is_some_and(|include| !include.is_empty());
+        let mut cells = if only_includes {
+            Vec::new()
+        } else {
+            vec![Env::new()]
+        };
+
+        for (name, vals) in self.vars.iter() {
+            cells = cells
+                .into_iter()
+                .flat_map(|cell| {
+                    vals.iter().map(move |val| {
+                        let mut cell = cell.clone();
+                        cell.insert(name.clone(), val.clone());
+                        cell
+                    })
+                })
+                .collect();
+        }
+
+        if let Some(exclude) = &self.exclude {
+            cells.retain(|cell| {
+                !exclude
+                    .iter()
+                    .any(|ex| ex.iter().all(|(name, val)| cell.get(name) == Some(val)))
+            });
+        }
+
+        for cell in self.include.iter().flatten() {
+            if !cells.contains(cell) {
+                cells.push(cell.clone());
+            }
+        }
+
+        cells
+    }
+
+    /// every variable name, including ones only used by `include`.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.vars.keys().cloned().collect();
+
+        for name in self.include.iter().flatten().flat_map(|cell| cell.keys()) {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+
+        names
+    }
+}
+
+/// how a job or one of its matrix combinations went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    /// a step failed.
+    Failed,
+    /// ci itself failed, e.g. the runner image didn't build.
+    Error,
+    /// not run because an earlier job or combination failed.
+    Skipped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::Error => write!(f, "error"),
+            Outcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+impl Outcome {
+    /// the outcome of several jobs or combinations. an error outweighs a failure, skips don't
+    /// count.
+    pub fn combine(outcomes: impl IntoIterator<Item = Outcome>) -> Outcome {
+        outcomes
+            .into_iter()
+            .fold(Outcome::Passed, |combined, outcome| {
+                match (combined, outcome) {
+                    (Outcome::Error, _) | (_, Outcome::Error) => Outcome::Error,
+                    (Outcome::Failed, _) | (_, Outcome::Failed) => Outcome::Failed,
+                    _ => Outcome::Passed,
+                }
+            })
+    }
+}
+
+/// a matrix combination as `${matrix.NAME}` variables.
+pub fn matrix_vars(cell: &Env) -> Env {
+    cell.iter()
+        .map(|(name, val)| (format!("matrix.{name}"), val.clone()))
+        .collect()
+}
+
+pub fn matrix_label(cell: &Env) -> String {
+    cell.iter()
+        .map(|(name, val)| format!("{name}={val}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// the results of every combination as a table for discord.
+fn matrix_table(matrix: &Matrix, results: &[CellRecord]) -> String {
+    let names = matrix.names();
+    let mut header = names.clone();
+    header.push("result".into());
+
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|res| {
+            let mut row: Vec<String> = names
+                .iter()
+                .map(|name| res.matrix.get(name).cloned().unwrap_or("-".into()))
+                .collect();
+            row.push(res.outcome.to_string());
+            row
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].len())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let fmt_row = |row: &[String]| {
+        row.iter()
+            .zip(widths.iter())
+            .map(|(col, width)| format!("{col:width$}"))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut table = vec![fmt_row(&header)];
+    table.push(
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-|-"),
+    );
+    table.extend(rows.iter().map(|row| fmt_row(row)));
+
+    format!("```\n{}\n```", table.join("\n"))
+}
+
+/// a script entry, either a plain command or a table with per step settings.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Step {
+    Command(String),
+    Detailed(Box<StepConfig>),
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize, JsonSchema,
+)]
+#[schemars(deny_unknown_fields)]
+pub struct StepConfig {
+    /// what the runner reports the step as. defaults to the command.
+    pub name: Option<String>,
+    pub run: String,
+    pub shell: Option<Shell>,
+    /// relative to the repo root, which is also the default.
+    pub working_directory: Option<PathBuf>,
+    /// keep going with the next step if this one fails.
+    pub continue_on_error: Option<bool>,
+    /// environment variables for this step only.
+    pub env: Option<Env>,
+    /// runs the step in this image instead of the pipelines container. the workspace is shared.
+    pub image: Option<String>,
+    /// seconds the step may run before it's killed.
+    pub timeout: Option<u64>,
+    /// only run the step if this expression is true. unless it checks `success()`, `failure()`
+    /// or `always()`, the steps before it must also have passed.
+    #[serde(rename = "if")]
+    pub condition: Option<String>,
+    /// runs the step again if it fails.
+    pub retry: Option<Retry>,
+}
+
+/// how a step or pipeline failed, for `retry`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// a step exited non-zero or was killed.
+    ExitCode,
+    /// a step ran past its `timeout`.
+    Timeout,
+    /// ci itself failed, e.g. an image didn't pull or a step's shell is missing.
+    Error,
+}
+
+/// when to try a failed step or pipeline again.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Retry {
+    /// attempts in total, counting the first.
+    #[schemars(range(min = 1, max = 10))]
+    pub max_attempts: u32,
+    /// seconds to wait before the first retry, doubled for every one after it up to an hour.
+    /// defaults to 0.
+    pub backoff: Option<u64>,
+    /// the failures to retry. defaults to all of them.
+    pub on: Option<Vec<FailureKind>>,
+    /// only retry `exit_code` failures with one of these codes. defaults to any code.
+    pub exit_codes: Option<Vec<i32>>,
+}
+
+impl Retry {
+    /// whether failing attempt `attempt` (from 1) like this gets another try.
+    pub fn should_retry(&self, attempt: u32, kind: FailureKind, exit_code: Option<i32>) -> bool {
+        attempt < self.max_attempts
+            && self.on.as_ref().is_none_or(|on| on.contains(&kind))
+            && (kind != FailureKind::ExitCode
+                || self
+                    .exit_codes
+                    .as_ref()
+                    .is_none_or(|codes| exit_code.is_some_and(|code| codes.contains(&code))))
+    }
+
+    /// how long to wait after failing attempt `attempt`, at most `MAX_RETRY_DELAY`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff.unwrap_or(0);
+        let delay =
+            Duration::from_secs(backoff.saturating_mul(1 << attempt.saturating_sub(1).min(16)));
+
+        delay.min(MAX_RETRY_DELAY)
+    }
+}
+
+/// what a step's `run` is executed with.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    #[default]
+    Sh,
+    Bash,
+    Python,
+}
+
+impl Shell {
+    /// the program and the flag that makes it run a script passed as the next argument.
+    pub fn program(&self) -> (&'static str, &'static str) {
+        match self {
+            Shell::Sh => ("sh", "-c"),
+            Shell::Bash => ("bash", "-c"),
+            Shell::Python => ("python3", "-c"),
+        }
+    }
+}
+
+impl Step {
+    /// the step as a table. plain commands get the defaults.
+    pub fn config(&self) -> StepConfig {
+        match self {
+            Step::Command(cmd) => StepConfig {
+                run: cmd.clone(),
+                ..Default::default()
+            },
+            Step::Detailed(step) => step.as_ref().clone(),
+        }
+    }
+}
+
+impl StepConfig {
+    /// the name the step is reported by.
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.run)
+    }
+}
+
+/// what started a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunEvent {
+    /// the poller found new commits.
+    Push,
+    /// someone used `/run`, or `dcicd-runner` locally.
+    Manual,
+}
+
+impl fmt::Display for RunEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunEvent::Push => write!(f, "push"),
+            RunEvent::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+/// what is being run and why. handed to the runner as the `DCICD_*` built-in variables.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RunContext {
+    pub run_id: RunId,
+    pub repo: Repo,
+    pub commit_sha: String,
+    pub branch: String,
+    /// a tag pointing at the commit.
+    pub tag: Option<String>,
+    pub pipeline_name: PipelineName,
+    pub triggered_by: String,
+    pub event: RunEvent,
+    /// the guild the run was started from, used to look up guild secrets.
+    pub guild_id: Option<GuildId>,
+    /// the run this one runs again.
+    pub rerun_of: Option<RunId>,
+    /// where the run was started from. approvals are asked for there.
+    pub channel_id: Option<ChannelId>,
+    /// the checked inputs of the run, with defaults filled in.
+    pub inputs: Env,
+    /// what the steps of the runs earlier jobs wrote to `$DCICD_OUTPUT`.
+    pub outputs: Env,
+}
+
+impl RunContext {
+    /// the built-in variables, inputs and outputs of earlier jobs included.
+    pub fn vars(&self) -> Env {
+        let mut vars = Env::from([
+            ("DCICD_RUN_ID".into(), self.run_id.to_string()),
+            ("DCICD_REPO".into(), self.repo.repo_name.clone()),
+            ("DCICD_COMMIT_SHA".into(), self.commit_sha.clone()),
+            ("DCICD_BRANCH".into(), self.branch.clone()),
+            ("DCICD_TAG".into(), self.tag.clone().unwrap_or_default()),
+            ("DCICD_PIPELINE".into(), self.pipeline_name.clone()),
+            ("DCICD_TRIGGERED_BY".into(), self.triggered_by.clone()),
+            ("DCICD_EVENT".into(), self.event.to_string()),
+        ]);
+        vars.extend(
+            self.inputs
+                .iter()
+                .map(|(name, value)| (input_var(name), value.clone())),
+        );
+        vars.extend(output_vars(&self.outputs));
+
+        vars
+    }
+}
+
+/// expands `${VAR}` using `vars`. unknown variables are left alone so the shell can still expand
+/// them, and `$${VAR}` escapes to a literal `${VAR}`.
+pub fn interpolate(s: &str, vars: &Env) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let var = &rest[start..=start + len];
+        let name = &var[2..var.len() - 1];
+
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str(var);
+        } else {
+            out.push_str(&rest[..start]);
+            out.push_str(vars.get(name).map(|val| val.as_str()).unwrap_or(var));
+        }
+
+        rest = &rest[start + len + 1..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// adds `env` to `vars`, interpolating its values against what was already in `vars`. built-in
+/// variables can't be overridden.
+pub fn extend_env(vars: &mut Env, env: &Env) {
+    let resolved: Vec<(String, String)> = env
+        .iter()
+        .filter(|(name, _)| !name.starts_with(BUILTIN_VAR_PREFIX))
+        .map(|(name, val)| (name.clone(), interpolate(val, vars)))
+        .collect();
+
+    vars.extend(resolved);
+}
+
+/// hands out the next run id. persisted under `STATE_DIR` so ids stay unique across restarts.
+pub fn next_run_id() -> Result<RunId> {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(RUN_ID_FILE);
+
+    let last: RunId = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|id| id.trim().parse().ok())
+        .unwrap_or(0);
+
+    create_dir_all(STATE_DIR)?;
+    write(&path, (last + 1).to_string())?;
+
+    Ok(last + 1)
+}
+
+/// puts the workspace back to a clean checkout of HEAD, for the next job of a run.
+fn reset_workspace() -> Result<()> {
+    let repo = Repository::open(CACHE_DIR)?;
+    let head = repo.head()?.peel_to_commit()?;
+    repo.reset(head.as_object(), ResetType::Hard, None)?;
+
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).include_ignored(true);
+
+    for entry in repo.statuses(Some(&mut opts))?.iter() {
+        if !entry.status().intersects(Status::WT_NEW | Status::IGNORED) {
+            continue;
+        }
+
+        let Some(path) = entry.path() else {
+            continue;
+        };
+        let path = Path::new(CACHE_DIR).join(path);
+
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// the commit sha and branch (if not detached) checked out in the workspace.
+pub fn workspace_head(workspace: &Path) -> Result<(String, Option<String>)> {
+    let repo = Repository::open(workspace)?;
+    let head = repo.head()?;
+    let sha = head.peel_to_commit()?.id().to_string();
+    let branch = if head.is_branch() {
+        head.shorthand().map(|branch| branch.to_string())
+    } else {
+        None
+    };
+
+    Ok((sha, branch))
+}
+
+/// a tag pointing at the workspace HEAD, the first by name if there are several.
+pub fn workspace_tag(workspace: &Path) -> Option<String> {
+    let repo = Repository::open(workspace).ok()?;
+    let head = repo.head().ok()?.peel_to_commit().ok()?.id();
+    let mut tags: Vec<String> = repo
+        .tag_names(None)
+        .ok()?
+        .iter()
+        .flatten()
+        .filter(|tag| {
+            repo.revparse_single(&format!("refs/tags/{tag}"))
+                .and_then(|obj| obj.peel_to_commit())
+                .is_ok_and(|commit| commit.id() == head)
+        })
+        .map(|tag| tag.to_string())
+        .collect();
+    tags.sort();
+
+    tags.into_iter().next()
+}
+
+/// what a finished run reports back to whoever started it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunReport {
+    pub msg: String,
+    /// files to attach to the message.
+    pub attachments: Vec<PathBuf>,
+    /// the run reported on, so it can be re-run from the message.
+    pub run_id: Option<RunId>,
+    pub outcome: Option<Outcome>,
+    /// the markdown steps wrote to `$DCICD_SUMMARY`.
+    pub summary: Option<String>,
+}
+
+impl From<String> for RunReport {
+    fn from(msg: String) -> Self {
+        Self {
+            msg,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for RunReport {
+    fn from(msg: &str) -> Self {
+        msg.to_string().into()
+    }
+}
+
+/// an earlier run to run again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rerun {
+    pub run_id: RunId,
+    /// only run the jobs and matrix combinations that didn't pass. the artifacts of the rest are
+    /// copied from the earlier run.
+    pub failed_only: bool,
+}
+
+/// a pipeline run waiting for the backend to become free.
+#[derive(Debug, Clone)]
+pub struct QueuedRun {
+    pub repo: Repo,
+    pub branch: String,
+    pub commit: String,
+    pub pipeline_name: PipelineName,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: Option<ChannelId>,
+    /// who (or what) queued the run.
+    pub triggered_by: String,
+    pub event: RunEvent,
+    pub rerun: Option<Rerun>,
+    /// input name -> value, checked once the pipeline is loaded.
+    pub inputs: Env,
+    /// where the report goes. the backends output if not set.
+    pub report: Option<Sender<RunReport>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
@@ -58,12 +844,26 @@ pub enum CiCdCmd {
     Clone(Url),
     RunPipeline {
         pipeline_name: PipelineName,
+        /// the branch being built, if the workspace HEAD is detached.
+        branch: Option<String>,
+        /// who (or what) started the run.
+        triggered_by: String,
+        event: RunEvent,
+        guild_id: Option<GuildId>,
+        channel_id: Option<ChannelId>,
+        rerun: Option<Rerun>,
+        /// input name -> value, checked once the pipeline is loaded.
+        inputs: Env,
         // token: String,
         // ctx: ,
-        on_complete: Box<dyn Fn(String) + Send + Sync>,
+        on_complete: OnComplete,
         // on_complete: Context<'a>,
     },
     GetLogs,
+    /// queue a pipeline to run once the backend is free.
+    Enqueue(QueuedRun),
+    /// rebuild the runner images from scratch on the next run.
+    RebuildRunner,
 }
 
 // #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -80,6 +880,12 @@ pub struct Backend {
     // pub input: Receiver<CiCdCmd>,
     pub output: Sender<String>,
     pub logs: Arc<Mutex<String>>,
+    /// runs waiting for the current pipeline to finish.
+    pub queue: VecDeque<QueuedRun>,
+    /// set by `CiCdCmd::RebuildRunner`, cleared once a run picks it up.
+    pub rebuild_runner: bool,
+    /// where approval jobs ask for a decision.
+    pub approvals: Option<Sender<ApprovalRequest>>,
 }
 
 impl Backend {
@@ -88,11 +894,90 @@ impl Backend {
         Self {
             state: Arc::new(Mutex::new(BackendState::default())),
             repos: HashMap::default(),
-            jh: spawn(async { () }),
+            jh: spawn(async {}),
             // input,
             output,
             logs: Arc::new(Mutex::new(String::default())),
+            queue: VecDeque::default(),
+            rebuild_runner: false,
+            approvals: None,
+        }
+    }
+
+    /// true if a pipeline is currently running.
+    pub async fn is_busy(&self) -> bool {
+        !self.jh.is_finished()
+            || matches!(
+                *self.state.lock().await,
+                BackendState::RunningPipeline { .. }
+            )
+    }
+
+    /// loads the queued runs repo at the requested commit and starts the pipeline.
+    async fn start_queued(&mut self, queued: QueuedRun) -> Result<()> {
+        let QueuedRun {
+            repo,
+            branch,
+            commit,
+            pipeline_name,
+            guild_id,
+            channel_id,
+            triggered_by,
+            event,
+            rerun,
+            inputs,
+            report,
+        } = queued;
+
+        println!(
+            "starting queued pipeline {pipeline_name} for {}@{commit}",
+            repo.repo_name
+        );
+
+        {
+            let mut s = self.state.lock().await;
+            *s = BackendState::Available { repo: repo.clone() };
         }
+
+        if let Err(e) = clone_repo(&repo.url, Some(&commit)).await {
+            self.output
+                .send(format!("failed to clone repo: {}", e))
+                .unwrap();
+
+            if let Some(report) = report {
+                let _ = report.send(format!("failed to clone repo: {}", e).into());
+            }
+
+            bail!(format!("failed to clone repo: {}", e));
+        }
+
+        let output = self.output.clone();
+        let label = format!("[{}:{branch}@{commit:.8}] {pipeline_name}", repo.repo_name);
+        let on_complete: OnComplete = match report {
+            Some(report) => Box::new(move |msg| {
+                if let Err(e) = report.send(msg) {
+                    println!("{e}")
+                }
+            }),
+            None => Box::new(move |report: RunReport| {
+                if let Err(e) = output.send(format!("{label}: {}", report.msg)) {
+                    println!("{e}")
+                }
+            }),
+        };
+
+        self.process(CiCdCmd::RunPipeline {
+            pipeline_name,
+            branch: Some(branch),
+            triggered_by,
+            event,
+            guild_id,
+            channel_id,
+            rerun,
+            inputs,
+            on_complete,
+        })
+        .await
     }
 
     pub async fn process(&mut self, msg: CiCdCmd) -> Result<()> {
@@ -104,6 +989,13 @@ impl Backend {
             // CiCdCmd::RunPipeline(pipeline_name) => {
             CiCdCmd::RunPipeline {
                 pipeline_name,
+                branch,
+                triggered_by,
+                event,
+                guild_id,
+                channel_id,
+                rerun,
+                inputs,
                 on_complete,
             } => {
                 println!("pre-repo");
@@ -118,55 +1010,154 @@ impl Backend {
                 println!("{repo:?}");
 
                 // load repos pipeline file.
-                let mut pipeline_file: PathBuf = PathBuf::from(CACHE_DIR);
-                pipeline_file.push(PIPELINE_FILE);
-
-                let Ok(pipelines) = toml::from_str::<Pipelines>(
-                    &read_to_string(&pipeline_file)
-                        .await
-                        .unwrap_or(String::new()),
-                ) else {
-                    self.output
-                        .send(format!("failed to read {PIPELINE_FILE}"))
-                        .unwrap();
-                    bail!(format!(
-                        "failed to read {PIPELINE_FILE}, {:?}",
-                        toml::from_str::<Pipelines>(
-                            &read_to_string(&pipeline_file)
-                                .await
-                                .unwrap_or(String::new())
-                        )
-                    ));
+                let (file, source) = match read_pipeline_file(Path::new(CACHE_DIR)) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        self.output.send(e.to_string()).unwrap();
+                        on_complete(e.to_string().into());
+                        bail!(e);
+                    }
                 };
 
-                // find pipline
-                let Some(pipeline) = pipelines.get(&pipeline_name).map(|pl| pl.to_owned()) else {
-                    self.output
-                        .send(format!("unknown pipeline: {pipeline_name}"))
-                        .unwrap();
-                    bail!(format!("unknown pipeline: {pipeline_name}"));
+                let loaded = {
+                    let includes = Includes::from_dir(Path::new(CACHE_DIR), self.repos.clone());
+                    lint::load(file, &source, &includes)
+                };
+                let pipelines = match loaded {
+                    Ok((pipelines, warnings)) => {
+                        if !warnings.is_empty() {
+                            self.output.send(lint::discord_report(&warnings)).unwrap();
+                        }
+
+                        pipelines
+                    }
+                    Err(diagnostics) => {
+                        on_complete(
+                            format!("{file} is invalid:\n{}", lint::discord_report(&diagnostics))
+                                .into(),
+                        );
+                        bail!("{file} is invalid:\n{}", lint::report(&diagnostics));
+                    }
+                };
+
+                // find pipline, and the ones it needs artifacts from
+                let job_names = match job_order(&pipelines, &pipeline_name) {
+                    Ok(job_names) => job_names,
+                    Err(e) => {
+                        self.output.send(e.to_string()).unwrap();
+                        on_complete(format!("can't run {pipeline_name}, {e}.").into());
+                        bail!(e);
+                    }
+                };
+
+                let inputs =
+                    match inputs::resolve(pipelines[&pipeline_name].inputs.as_ref(), &inputs) {
+                        Ok(inputs) => inputs,
+                        Err(e) => {
+                            on_complete(format!("can't run {pipeline_name}, {e}.").into());
+                            bail!("can't run {pipeline_name}, {e}");
+                        }
+                    };
+
+                // failed only re-runs pick up the passed jobs of the earlier run.
+                let previous = match rerun {
+                    Some(Rerun {
+                        run_id,
+                        failed_only: true,
+                    }) => match history::load(run_id) {
+                        Ok(record) => Some(record),
+                        Err(e) => {
+                            on_complete(e.to_string().into());
+                            bail!(e);
+                        }
+                    },
+                    _ => None,
+                };
+
+                let (commit_sha, head_branch) = match workspace_head(Path::new(CACHE_DIR)) {
+                    Ok(head) => head,
+                    Err(e) => {
+                        on_complete(format!("can't run {pipeline_name}, {e}.").into());
+                        bail!(e);
+                    }
+                };
+                let run_id = match next_run_id() {
+                    Ok(run_id) => run_id,
+                    Err(e) => {
+                        on_complete(format!("can't run {pipeline_name}, {e}.").into());
+                        bail!(e);
+                    }
                 };
+                let ctx = RunContext {
+                    run_id,
+                    repo: repo.clone(),
+                    commit_sha,
+                    branch: branch.or(head_branch).unwrap_or("HEAD".into()),
+                    tag: workspace_tag(Path::new(CACHE_DIR)),
+                    pipeline_name: pipeline_name.clone(),
+                    triggered_by,
+                    event,
+                    guild_id,
+                    rerun_of: rerun.map(|rerun| rerun.run_id),
+                    channel_id,
+                    inputs,
+                    outputs: Env::new(),
+                };
+                let mut jobs = Vec::new();
+
+                for name in job_names {
+                    let pipeline = pipelines[&name].clone();
+                    let secrets = match &pipeline.secrets {
+                        Some(names) => match SecretStore::load()
+                            .and_then(|store| store.resolve(&repo.repo_name, guild_id, names))
+                        {
+                            Ok(secrets) => secrets,
+                            Err(e) => {
+                                self.output
+                                    .send(format!("failed to load secrets: {e}"))
+                                    .unwrap();
+                                on_complete(format!("can't run {pipeline_name}, {e}.").into());
+                                bail!(format!("failed to load secrets: {e}"));
+                            }
+                        },
+                        None => Env::new(),
+                    };
+
+                    let previous = previous.as_ref().and_then(|record| {
+                        let job = record.jobs.iter().find(|job| job.name == name)?;
+
+                        Some((record.run_id, job.clone()))
+                    });
+
+                    jobs.push(Job {
+                        name,
+                        pipeline,
+                        secrets,
+                        previous,
+                    });
+                }
 
-                // TODO: build docker container
                 let launcher = Launcher::new(Command {
                     program: PathBuf::from("/usr/bin/docker"),
                     ..Default::default()
                 });
-                launcher
-                    .build(BuildOpt {
-                        build_args: vec![("BASE_IMAGE".into(), pipeline.container)],
-                        context: PathBuf::from("/etc/dcicd/docker/"),
-                        tag: Some("dcicd".into()),
-                        no_cache: true,
-                        ..Default::default()
-                    })
-                    .run()?;
 
                 // TODO: run pipeline
                 let state = self.state.clone();
                 let logs = self.logs.clone();
 
-                self.jh = spawn(run(state, logs, on_complete, repo, pipeline_name, launcher));
+                let rebuild_runner = std::mem::take(&mut self.rebuild_runner);
+                let runners = Runners::new(launcher, PathBuf::from(CACHE_DIR), rebuild_runner);
+
+                self.jh = spawn(run(
+                    state,
+                    logs,
+                    on_complete,
+                    ctx,
+                    jobs,
+                    runners,
+                    self.approvals.clone(),
+                ));
             }
             CiCdCmd::Clone(url) => {
                 match *self.state.lock().await {
@@ -174,93 +1165,1078 @@ impl Backend {
                     _ => bail!("backend is not available to clone at the moment. pls wait for job to finish."),
                 }
 
-                // RM storage dir
-                let path = Path::new(CACHE_DIR);
-
-                if path.exists() {
-                    remove_dir_all(path)
-                        .await
-                        .expect("Could not remove old socket!");
-                }
-
-                // clone repo to storage dir
-                if let Err(e) = Repository::clone(&url.to_string(), CACHE_DIR) {
+                if let Err(e) = clone_repo(&url, None).await {
                     self.output
                         .send(format!("failed to clone repo: {}", e))
                         .unwrap();
                     bail!(format!("failed to clone repo: {}", e));
                 }
             }
+            CiCdCmd::Enqueue(queued) => {
+                println!(
+                    "queued pipeline {} for {}@{}",
+                    queued.pipeline_name, queued.repo.repo_name, queued.commit
+                );
+                self.queue.push_back(queued);
+            }
+            CiCdCmd::RebuildRunner => {
+                println!("runner images will be rebuilt on the next run");
+                self.rebuild_runner = true;
+            }
         };
 
         Ok(())
     }
 }
 
-async fn run(
-    state: Arc<Mutex<BackendState>>,
-    logs: Arc<Mutex<String>>,
-    on_complete: Box<dyn Fn(String) + Send + Sync>,
-    repo: Repo,
-    pipeline_name: String,
-    launcher: Launcher,
-) {
-    {
-        let mut s = state.lock().await;
-        *s = BackendState::RunningPipeline {
-            repo: repo.clone(),
-            pipeline: pipeline_name.clone(),
-        };
+/// wipes the workspace and clones `url` into it. checks out `commit` if one is given.
+async fn clone_repo(url: &Url, commit: Option<&str>) -> Result<()> {
+    // RM storage dir
+    let path = Path::new(CACHE_DIR);
+
+    if path.exists() {
+        remove_dir_all(path)
+            .await
+            .expect("Could not remove old socket!");
     }
 
-    // mount the git repo as a volume in a custom docker container at:
-    // /home/dcicd-runner/repo/. have the docker container run the CiCd pipeline.
-    // docker build docker-files/runner/. --build-arg="BASE_IMAGE=rust" -t test-runner
-    let res = launcher
-        .run(RunOpt {
-            image: "dcicd".into(),
-            remove: true,
-            volumes: vec![Volume {
-                src: PathBuf::from(CACHE_DIR),
-                dst: PathBuf::from("/home/dcicd-runner/repo/"),
-                read_write: true,
-                ..Default::default()
-            }],
-            command: Some(pipeline_name.into()),
-            ..Default::default()
-        })
-        .combine_output()
-        .enable_capture()
-        .run();
-
-    let mut l = logs.lock().await;
-
-    // if let Err(e) = res.map(|res| {
-    //     *l = String::from_utf8_lossy(&res.stdout).to_string();
-    //
-    //     on_complete(&format!("pipline run completed sucessfully.")).await;
-    //     // on_complete();
-    // }) {
-    //     eprintln!("failed to launch runner. failed with error, {e}");
-    //     *l = "failed to launch runner (Docker/Podman). failed with error, {e}".into();
-    // }
-
-    match res {
-        Ok(res) => {
-            *l = String::from_utf8_lossy(&res.stdout).to_string();
-
-            on_complete(format!(
-                "pipline run completed sucessfully. use `/logs` to view logs."
-            ));
-            // on_complete();
+    // clone repo to storage dir
+    let repo = Repository::clone(url.as_str(), CACHE_DIR)?;
+
+    if let Some(commit) = commit {
+        let obj = repo.revparse_single(commit)?;
+        repo.checkout_tree(&obj, None)?;
+        repo.set_head_detached(obj.id())?;
+    }
+
+    Ok(())
+}
+
+/// the id (digest) of a local image.
+fn image_id(launcher: &Launcher, image: &str) -> Result<String> {
+    let mut inspect = launcher.base_command().clone();
+    inspect
+        .add_arg_pair("image", "inspect")
+        .add_arg_pair("--format", "{{.Id}}")
+        .add_arg(image)
+        .enable_capture();
+    inspect.log_command = false;
+    inspect.log_output_on_error = false;
+
+    Ok(inspect.run()?.stdout_string_lossy().trim().to_string())
+}
+
+/// the runner image for a pipelines container. it's only built when the base image, the
+/// `dcicd-runner` binary or the dockerfile changed since the last build. `rebuild` pulls the base
+/// image and builds without dockers layer cache. returns the image tag.
+fn runner_image(launcher: &Launcher, base_image: &str, rebuild: bool) -> Result<String> {
+    let mut base_id = image_id(launcher, base_image).ok();
+
+    if rebuild || base_id.is_none() {
+        let mut pull = launcher.base_command().clone();
+        pull.add_arg_pair("pull", base_image);
+
+        // images only built locally can't be pulled, the local one is fine for those.
+        if let Err(e) = pull.run() {
+            if base_id.is_none() {
+                bail!("failed to pull {base_image}. {e}");
+            }
+        }
+
+        base_id = Some(image_id(launcher, base_image)?);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(base_id.unwrap_or_default());
+    hasher.update(image_id(launcher, RUNNER_BIN_IMAGE)?);
+    hasher.update(std::fs::read(Path::new(RUNNER_CONTEXT).join("Dockerfile")).unwrap_or_default());
+    let tag = format!("{RUNNER_IMAGE}:{:.16}", format!("{:x}", hasher.finalize()));
+
+    if rebuild || image_id(launcher, &tag).is_err() {
+        println!("building runner image {tag} for {base_image}");
+
+        launcher
+            .build(BuildOpt {
+                build_args: vec![("BASE_IMAGE".into(), base_image.into())],
+                context: PathBuf::from(RUNNER_CONTEXT),
+                tag: Some(tag.clone()),
+                no_cache: rebuild,
+                ..Default::default()
+            })
+            .run()?;
+    }
+
+    Ok(tag)
+}
+
+/// consecutive steps that run in the same image, as (base image, first step, last step). steps
+/// are numbered from 1.
+fn step_segments(pipeline: &Pipeline, vars: &Env) -> Vec<(String, usize, usize)> {
+    let mut segments: Vec<(String, usize, usize)> = Vec::new();
+
+    for (i, step) in pipeline.steps().iter().enumerate() {
+        let image = interpolate(step.image.as_ref().unwrap_or(&pipeline.container), vars);
+
+        match segments.last_mut() {
+            Some((last, _, end)) if *last == image => *end = i + 1,
+            _ => segments.push((image, i + 1, i + 1)),
+        }
+    }
+
+    segments
+}
+
+/// what runner containers are started with.
+pub struct Runners {
+    pub launcher: Launcher,
+    /// mounted into the runners as the repo.
+    pub workspace: PathBuf,
+    /// rebuild each runner image once from scratch.
+    pub rebuild: bool,
+    /// base images -> the runner images already resolved.
+    pub images: HashMap<String, String>,
+    /// the network of the jobs services.
+    pub network: Option<String>,
+}
+
+impl Runners {
+    pub fn new(launcher: Launcher, workspace: PathBuf, rebuild: bool) -> Self {
+        Self {
+            launcher,
+            workspace,
+            rebuild,
+            images: HashMap::new(),
+            network: None,
+        }
+    }
+}
+
+/// runs every matrix combination of a job, appending the runners output to `output`.
+/// `after_failure` is whether an earlier job of the run failed, for the pipelines `if`.
+pub fn run_job(
+    ctx: &RunContext,
+    job: &Job,
+    runners: &mut Runners,
+    output: &mut String,
+    after_failure: bool,
+) -> Vec<CellRecord> {
+    let pipeline = &job.pipeline;
+    let (cells, fail_fast) = match &pipeline.matrix {
+        Some(matrix) => (matrix.cells(), matrix.fail_fast.unwrap_or(true)),
+        None => (vec![Env::new()], true),
+    };
+    let mut results: Vec<CellRecord> = Vec::new();
+
+    for cell in cells {
+        // passed combinations of a failed only re-run are kept as they were.
+        let passed = job.previous.as_ref().and_then(|(run_id, previous)| {
+            let passed = previous
+                .cells
+                .iter()
+                .find(|prev| prev.matrix == cell && prev.outcome == Outcome::Passed)?;
+
+            Some((run_id, passed.clone()))
+        });
+
+        if let Some((run_id, passed)) = passed {
+            if pipeline.matrix.is_some() {
+                output.push_str(&format!(
+                    "=== matrix: {}, passed in run {run_id}\n",
+                    matrix_label(&cell)
+                ));
+            }
+
+            results.push(passed);
+            continue;
+        }
+
+        if fail_fast
+            && results
+                .iter()
+                .any(|res| matches!(res.outcome, Outcome::Failed | Outcome::Error))
+        {
+            results.push(CellRecord {
+                matrix: cell,
+                outcome: Outcome::Skipped,
+                reason: None,
+                steps: Vec::new(),
+                retries: Vec::new(),
+            });
+            continue;
+        }
+
+        if pipeline.matrix.is_some() {
+            output.push_str(&format!("=== matrix: {}\n", matrix_label(&cell)));
+        }
+
+        let mut vars = ctx.vars();
+        vars.extend(matrix_vars(&cell));
+
+        if let Some(env) = &pipeline.env {
+            extend_env(&mut vars, env);
+        }
+
+        let skipped = match should_run(pipeline.condition.as_deref(), &vars, after_failure) {
+            Ok(true) => None,
+            Ok(false) => Some((Outcome::Skipped, "skipped, `if` is false.".to_string())),
+            Err(e) => Some((Outcome::Error, format!("invalid `if`. {e}"))),
+        };
+
+        if let Some((outcome, reason)) = skipped {
+            output.push_str(&format!("=== {reason}\n"));
+            results.push(CellRecord {
+                matrix: cell,
+                outcome,
+                reason: Some(reason),
+                steps: Vec::new(),
+                retries: Vec::new(),
+            });
+            continue;
+        }
+
+        let mut env = ctx.vars();
+
+        if let Some(services) = &pipeline.services {
+            env.extend(service_vars(services));
+        }
+
+        if !cell.is_empty() {
+            env.insert(
+                "DCICD_MATRIX".into(),
+                serde_json::to_string(&cell).unwrap_or_default(),
+            );
+        }
+
+        // failed attempts, for retries of the whole combination.
+        let mut retries = Vec::new();
+        let run = loop {
+            let run = run_cell(job, &vars, &env, runners, output);
+            let attempt = retries.len() as u32 + 1;
+
+            match (&pipeline.retry, run.failure) {
+                (Some(retry), Some((kind, exit_code)))
+                    if retry.should_retry(attempt, kind, exit_code) =>
+                {
+                    let delay = retry.delay(attempt);
+                    let reason = run.reason.unwrap_or_default();
+                    output.push_str(&format!(
+                        "=== retrying in {}, attempt {} of {}. {reason}\n",
+                        fmt_duration(delay.as_millis() as u64),
+                        attempt + 1,
+                        retry.max_attempts
+                    ));
+                    retries.push(reason);
+                    std::thread::sleep(delay);
+                }
+                _ => break run,
+            }
+        };
+
+        results.push(CellRecord {
+            matrix: cell,
+            outcome: run.outcome,
+            reason: run.reason,
+            steps: run.steps,
+            retries,
+        });
+    }
+
+    results
+}
+
+/// how one attempt at a matrix combination went.
+struct CellRun {
+    outcome: Outcome,
+    reason: Option<String>,
+    steps: Vec<StepResult>,
+    /// what failed and the exit code of the failed step, for retries. config errors are never
+    /// retried so they have none.
+    failure: Option<(FailureKind, Option<i32>)>,
+}
+
+/// runs the steps of one matrix combination, each run of steps with the same image in its own
+/// runner container.
+fn run_cell(
+    job: &Job,
+    vars: &Env,
+    env: &Env,
+    runners: &mut Runners,
+    output: &mut String,
+) -> CellRun {
+    let pipeline = &job.pipeline;
+    let mut outcome = Outcome::Passed;
+    let mut reason = None;
+    let mut failure = None;
+    let mut steps: Vec<StepResult> = Vec::new();
+    let all_steps = pipeline.steps();
+    // outputs of earlier containers are passed on to later ones.
+    let mut vars = vars.clone();
+    let mut outputs = Env::new();
+
+    // steps with their own image run in their own container against the same workspace.
+    for (base_image, first, last) in step_segments(pipeline, &vars) {
+        let segment = &all_steps[first - 1..last];
+        let failed = outcome == Outcome::Failed;
+
+        // after a failure only containers with `failure()` or `always()` steps are started.
+        // step envs aren't known here, so the runner decides for the steps it's given.
+        if failed
+            && !segment
+                .iter()
+                .any(|step| should_run(step.condition.as_deref(), &vars, true).unwrap_or(true))
+        {
+            for (i, step) in segment.iter().enumerate() {
+                let name = interpolate(step.name(), &vars);
+                output.push_str(&format!("=== step {}: {name} (skipped)\n", first + i));
+                steps.push(StepResult::skipped(first + i, name));
+            }
+            continue;
+        }
+
+        let image = match runners.images.get(&base_image) {
+            Some(image) => image.clone(),
+            None => match runner_image(&runners.launcher, &base_image, runners.rebuild) {
+                Ok(image) => {
+                    runners.images.insert(base_image.clone(), image.clone());
+                    image
+                }
+                Err(e) => {
+                    eprintln!("failed to build runner. failed with error, {e}");
+                    output.push_str(&format!(
+                        "failed to build runner image. failed with error, {e}\n"
+                    ));
+                    outcome = Outcome::Error;
+                    reason = Some(format!(
+                        "failed to build the runner image for {base_image}."
+                    ));
+                    failure = Some((FailureKind::Error, None));
+                    break;
+                }
+            },
+        };
+
+        let mut env = env.clone();
+        env.extend(output_vars(&outputs));
+        env.insert(STEPS_VAR.into(), format!("{first}-{last}"));
+        env.insert(IMAGE_VAR.into(), base_image);
+
+        if failed {
+            env.insert(STATUS_VAR.into(), "failure".into());
+        }
+
+        env.insert(
+            PIPELINE_VAR.into(),
+            serde_json::to_string(pipeline).unwrap_or_default(),
+        );
+
+        // mount the git repo as a volume in a custom docker container at:
+        // /home/dcicd-runner/repo/. have the docker container run the CiCd pipeline.
+        // docker build docker-files/runner/. --build-arg="BASE_IMAGE=rust" -t test-runner
+        let mut runner = runners.launcher.run(RunOpt {
+            image,
+            env: env
+                .into_iter()
+                .map(|(name, val)| (name.into(), val.into()))
+                .collect(),
+            remove: true,
+            volumes: vec![Volume {
+                src: runners.workspace.clone(),
+                dst: PathBuf::from(RUNNER_REPO_DIR),
+                read_write: true,
+                ..Default::default()
+            }],
+            network: runners.network.clone(),
+            command: Some("run".into()),
+            args: vec![
+                job.name.clone().into(),
+                "--repo".into(),
+                RUNNER_REPO_DIR.into(),
+                "--events".into(),
+            ],
+            ..Default::default()
+        });
+
+        // pass secrets through from our env (`--env NAME`) so they don't show up in `ps`.
+        for (name, val) in job.secrets.iter() {
+            runner.args.insert(1, "--env".into());
+            runner.args.insert(2, name.into());
+            runner.env.insert(name.into(), val.into());
+        }
+
+        match runner
+            .combine_output()
+            .enable_capture()
+            .disable_check()
+            .run()
+        {
+            Ok(res) => {
+                let (log, segment_steps) = parse_output(&String::from_utf8_lossy(&res.stdout));
+                output.push_str(&log);
+
+                for step in segment_steps.iter() {
+                    vars.extend(output_vars(&step.outputs));
+                    outputs.extend(step.outputs.clone());
+                }
+
+                steps.extend(segment_steps);
+
+                // the runner exits non-zero when a step fails. only steps that run after
+                // failures are left, in later containers.
+                if !res.status.success() {
+                    let exit = res.status.code().and_then(RunnerExit::from_code);
+                    let segment_outcome = exit.map(RunnerExit::outcome).unwrap_or(Outcome::Error);
+
+                    if segment_outcome == Outcome::Failed && failed {
+                        continue;
+                    }
+
+                    let failed_step = steps
+                        .iter()
+                        .find(|step| step.outcome == Outcome::Failed && !step.allowed_failure);
+
+                    outcome = segment_outcome;
+                    failure = match exit {
+                        Some(RunnerExit::ConfigError) => None,
+                        Some(RunnerExit::Timeout) => Some((FailureKind::Timeout, None)),
+                        Some(RunnerExit::StepFailed | RunnerExit::Signal) => Some((
+                            FailureKind::ExitCode,
+                            failed_step.and_then(|step| step.exit_code),
+                        )),
+                        _ => Some((FailureKind::Error, None)),
+                    };
+                    reason = Some(match (exit, failed_step) {
+                        (Some(RunnerExit::ConfigError), _) => {
+                            "the runner rejected the pipeline config, see the logs.".into()
+                        }
+                        (_, Some(step)) => step.failure(),
+                        (_, None) => match res.status.code() {
+                            Some(code) => format!("the runner exited with status {code}."),
+                            None => "the runner was killed.".into(),
+                        },
+                    });
+
+                    // ci errors end the job.
+                    if outcome != Outcome::Failed {
+                        break;
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("failed to launch runner. failed with error, {e}");
+                output.push_str(&format!(
+                    "failed to launch runner (Docker/Podman). failed with error, {e}\n"
+                ));
+                outcome = Outcome::Error;
+                reason = Some("failed to launch the runner.".into());
+                failure = Some((FailureKind::Error, None));
+                break;
+            }
+        }
+    }
+
+    CellRun {
+        outcome,
+        reason,
+        steps,
+        failure,
+    }
+}
+
+/// waits for someone to approve an approval job, if its `if` holds.
+async fn run_approval(
+    ctx: &RunContext,
+    job: &Job,
+    approval: &Approval,
+    approvals: Option<&Sender<ApprovalRequest>>,
+    after_failure: bool,
+    output: &mut String,
+) -> JobRecord {
+    let mut vars = ctx.vars();
+
+    if let Some(env) = &job.pipeline.env {
+        extend_env(&mut vars, env);
+    }
+
+    match should_run(job.pipeline.condition.as_deref(), &vars, after_failure) {
+        Ok(true) => {}
+        Ok(false) => {
+            output.push_str("=== skipped, `if` is false.\n");
+            return JobRecord::not_run(
+                &job.name,
+                Outcome::Skipped,
+                Some("skipped, `if` is false.".into()),
+            );
+        }
+        Err(e) => {
+            return JobRecord::not_run(
+                &job.name,
+                Outcome::Error,
+                Some(format!("invalid `if`. {e}")),
+            )
+        }
+    }
+
+    let (outcome, reason, record) = ask_for_approval(ctx, job, approval, approvals, output).await;
+
+    JobRecord {
+        name: job.name.clone(),
+        outcome,
+        reason: Some(reason),
+        cells: Vec::new(),
+        approval: record,
+        outputs: Env::new(),
+    }
+}
+
+/// waits for `job` to be approved. the outcome is passed if it was, the reason says who decided.
+async fn ask_for_approval(
+    ctx: &RunContext,
+    job: &Job,
+    approval: &Approval,
+    approvals: Option<&Sender<ApprovalRequest>>,
+    output: &mut String,
+) -> (Outcome, String, Option<ApprovalRecord>) {
+    let timeout = fmt_duration(approval.timeout().as_millis() as u64);
+    output.push_str(&format!("=== waiting up to {timeout} for approval\n"));
+
+    let decided = match approvals {
+        Some(approvals) => approval::wait_for_decision(approvals, ctx, &job.name, approval).await,
+        None => Err(anyhow!("approvals can only be asked for from discord")),
+    };
+    let record = match decided {
+        Ok(record) => record,
+        Err(e) => {
+            output.push_str(&format!("failed to ask for approval. {e}\n"));
+            return (
+                Outcome::Error,
+                format!("failed to ask for approval. {e}."),
+                None,
+            );
         }
+    };
+
+    let reason = match (&record.by, record.approved) {
+        (Some(by), true) => format!("approved by {by}."),
+        (Some(by), false) => format!("rejected by {by}."),
+        (None, _) => format!("nobody approved within {timeout}, rejected."),
+    };
+    output.push_str(&format!("=== {reason}\n"));
+
+    let outcome = if record.approved {
+        Outcome::Passed
+    } else {
+        Outcome::Failed
+    };
+
+    (outcome, reason, Some(record))
+}
+
+/// checks the protection rules of the environment `job` deploys to. `Err` is the record of a job
+/// that isn't allowed to run, `Ok` has the approval it got if one was needed.
+async fn protect_environment(
+    ctx: &RunContext,
+    job: &Job,
+    environment: &str,
+    approvals: Option<&Sender<ApprovalRequest>>,
+    after_failure: bool,
+    output: &mut String,
+) -> Result<Option<(String, ApprovalRecord)>, JobRecord> {
+    let mut vars = ctx.vars();
+
+    if let Some(env) = &job.pipeline.env {
+        extend_env(&mut vars, env);
+    }
+
+    // nothing is deployed if the job is skipped anyway, no need to ask.
+    if let Ok(false) = should_run(job.pipeline.condition.as_deref(), &vars, after_failure) {
+        return Ok(None);
+    }
+
+    let protection = match Environments::load() {
+        Ok(environments) => environments.protection(&ctx.repo.repo_name, environment),
         Err(e) => {
-            eprintln!("failed to launch runner. failed with error, {e}");
-            *l = "failed to launch runner (Docker/Podman). failed with error, {e}".into();
-            on_complete("pipline failed! use `/logs to view logs.".into());
+            output.push_str(&format!("failed to load environments. {e}\n"));
+            return Err(JobRecord::not_run(
+                &job.name,
+                Outcome::Error,
+                Some(format!("failed to load environments. {e}.")),
+            ));
+        }
+    };
+
+    if !protection.allows_branch(&ctx.branch) {
+        let reason = format!("`{}` can't deploy to {environment}.", ctx.branch);
+        output.push_str(&format!("=== {reason}\n"));
+        return Err(JobRecord::not_run(&job.name, Outcome::Failed, Some(reason)));
+    }
+
+    let Some(approval) = &protection.approval else {
+        return Ok(None);
+    };
+
+    output.push_str(&format!("=== deploying to {environment} needs approval\n"));
+
+    match ask_for_approval(ctx, job, approval, approvals, output).await {
+        (Outcome::Passed, reason, Some(record)) => Ok(Some((reason, record))),
+        (outcome, reason, record) => Err(JobRecord {
+            name: job.name.clone(),
+            outcome,
+            reason: Some(reason),
+            cells: Vec::new(),
+            approval: record,
+            outputs: Env::new(),
+        }),
+    }
+}
+
+async fn run(
+    state: Arc<Mutex<BackendState>>,
+    logs: Arc<Mutex<String>>,
+    on_complete: OnComplete,
+    mut ctx: RunContext,
+    jobs: Vec<Job>,
+    mut runners: Runners,
+    approvals: Option<Sender<ApprovalRequest>>,
+) {
+    let secrets: Env = jobs.iter().flat_map(|job| job.secrets.clone()).collect();
+    let on_complete = |report: RunReport| {
+        on_complete(RunReport {
+            msg: mask(&report.msg, &secrets),
+            summary: report.summary.map(|summary| mask(&summary, &secrets)),
+            ..report
+        })
+    };
+    let RunContext {
+        run_id,
+        repo,
+        commit_sha,
+        branch,
+        pipeline_name,
+        triggered_by,
+        event,
+        rerun_of,
+        inputs,
+        ..
+    } = ctx.clone();
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    let started = Instant::now();
+
+    {
+        let mut s = state.lock().await;
+        *s = BackendState::RunningPipeline {
+            repo: repo.clone(),
+            pipeline: pipeline_name.clone(),
+        };
+    }
+
+    let mut output = String::new();
+    let mut job_results: Vec<JobRecord> = Vec::new();
+    let mut details = Vec::new();
+    let mut collected = Vec::new();
+    let mut services = RunServices::new(run_id);
+
+    for (i, job) in jobs.iter().enumerate() {
+        // later jobs get the outputs of earlier ones.
+        ctx.outputs = job_results
+            .iter()
+            .flat_map(|res| res.outputs.clone())
+            .collect();
+
+        // passed jobs of a failed only re-run aren't run again, their artifacts are copied over.
+        if let Some((previous_id, previous)) = &job.previous {
+            if previous.outcome == Outcome::Passed {
+                let reused = match job.pipeline.artifacts {
+                    Some(_) => artifacts::reuse(*previous_id, run_id, &job.name),
+                    None => Ok(Vec::new()),
+                };
+
+                match reused {
+                    Ok(artifacts) => {
+                        output.push_str(&format!(
+                            "=== job: {}, passed in run {previous_id}\n",
+                            job.name
+                        ));
+                        collected.extend(artifacts);
+                        job_results.push(previous.clone());
+                        continue;
+                    }
+                    // the job is run again instead.
+                    Err(e) => output.push_str(&format!(
+                        "failed to copy the artifacts of {} from run {previous_id}. {e}\n",
+                        job.name
+                    )),
+                }
+            }
+        }
+
+        let failed = job_results
+            .iter()
+            .any(|res| matches!(res.outcome, Outcome::Failed | Outcome::Error));
+        // jobs whose `if` checks `failure()` or `always()` still run after earlier ones failed.
+        let checks_status = job
+            .pipeline
+            .condition
+            .as_deref()
+            .and_then(|condition| Expr::parse(condition).ok())
+            .is_some_and(|condition| condition.checks_status());
+
+        // jobs skipped by their own `if` don't count, only failures skip the rest.
+        if failed && !checks_status {
+            job_results.push(JobRecord::not_run(&job.name, Outcome::Skipped, None));
+            continue;
+        }
+
+        if jobs.len() > 1 {
+            output.push_str(&format!("=== job: {}\n", job.name));
+        }
+
+        let mut approved = None;
+
+        if let Some(environment) = &job.pipeline.environment {
+            let ctx = RunContext {
+                pipeline_name: job.name.clone(),
+                ..ctx.clone()
+            };
+
+            match protect_environment(
+                &ctx,
+                job,
+                environment,
+                approvals.as_ref(),
+                failed,
+                &mut output,
+            )
+            .await
+            {
+                Ok(approval) => approved = approval,
+                Err(result) => {
+                    job_results.push(result);
+                    continue;
+                }
+            }
+        }
+
+        if let Some(approval) = &job.pipeline.approval {
+            let ctx = RunContext {
+                pipeline_name: job.name.clone(),
+                ..ctx.clone()
+            };
+            let result =
+                run_approval(&ctx, job, approval, approvals.as_ref(), failed, &mut output).await;
+            job_results.push(result);
+            continue;
+        }
+
+        // every job starts from a clean checkout with only the artifacts it asked for.
+        let prepared = if i == 0 {
+            Ok(())
+        } else {
+            reset_workspace().and_then(|_| {
+                for producer in job.pipeline.uses_artifacts.iter().flatten() {
+                    artifacts::restore(run_id, producer, Path::new(CACHE_DIR))?;
+                }
+
+                Ok(())
+            })
+        };
+
+        if let Err(e) = prepared {
+            eprintln!("failed to prepare workspace for {}. {e}", job.name);
+            output.push_str(&format!("failed to prepare workspace. {e}\n"));
+            job_results.push(JobRecord::not_run(
+                &job.name,
+                Outcome::Error,
+                Some(format!("failed to prepare the workspace. {e}")),
+            ));
+            continue;
+        }
+
+        if let Some(job_services) = &job.pipeline.services {
+            if let Err(e) = services.start(&runners.launcher, &job.name, job_services) {
+                eprintln!("failed to start services of {}. {e}", job.name);
+                output.push_str(&format!("failed to start services. {e}\n"));
+                services.stop(&runners.launcher);
+                job_results.push(JobRecord::not_run(
+                    &job.name,
+                    Outcome::Error,
+                    Some(format!("failed to start services. {e}")),
+                ));
+                continue;
+            }
+        }
+
+        let ctx = RunContext {
+            pipeline_name: job.name.clone(),
+            ..ctx.clone()
+        };
+        let cache_key = job.pipeline.cache.as_ref().map(|config| {
+            let mut vars = ctx.vars();
+
+            if let Some(env) = &job.pipeline.env {
+                extend_env(&mut vars, env);
+            }
+
+            cache::resolve_key(&config.key, &vars, Path::new(CACHE_DIR))
+        });
+        let cache_hit = match &cache_key {
+            Some(key) => match cache::restore(&repo.repo_name, key, Path::new(CACHE_DIR)) {
+                Ok(hit) => {
+                    let status = if hit { "hit" } else { "miss" };
+                    output.push_str(&format!("cache {status}: {key}\n"));
+                    hit
+                }
+                Err(e) => {
+                    output.push_str(&format!("failed to restore cache {key}. {e}\n"));
+                    false
+                }
+            },
+            None => false,
+        };
+
+        runners.network = services.network();
+        // the runners block, so they're run off the runtime the approvals and reports share.
+        let running = {
+            let job = job.clone();
+            let mut output = std::mem::take(&mut output);
+
+            spawn_blocking(move || {
+                let results = run_job(&ctx, &job, &mut runners, &mut output, failed);
+                (results, runners, output)
+            })
+        };
+        let results = match running.await {
+            Ok((results, ran, ran_output)) => {
+                runners = ran;
+                output = ran_output;
+                results
+            }
+            Err(e) => resume_unwind(e.into_panic()),
+        };
+        services.stop(&runners.launcher);
+        let outcome = if results.iter().all(|res| res.outcome == Outcome::Skipped) {
+            Outcome::Skipped
+        } else {
+            Outcome::combine(results.iter().map(|res| res.outcome))
+        };
+
+        // only save on success so a broken run can't poison the cache.
+        if let (Some(config), Some(key)) = (&job.pipeline.cache, &cache_key) {
+            if outcome == Outcome::Passed && !cache_hit {
+                match cache::save(&repo.repo_name, key, Path::new(CACHE_DIR), &config.paths) {
+                    Ok((size, evicted)) => {
+                        output.push_str(&format!(
+                            "saved cache {key} ({})\n",
+                            artifacts::fmt_size(size)
+                        ));
+
+                        // other repos caches are only counted, their keys aren't this runs business.
+                        let (own, others): (Vec<_>, Vec<_>) = evicted
+                            .into_iter()
+                            .partition(|entry| entry.repo == repo.repo_name);
+
+                        for entry in own {
+                            output.push_str(&format!("evicted cache {}\n", entry.key));
+                        }
+
+                        if !others.is_empty() {
+                            output.push_str(&format!(
+                                "evicted {} caches of other repos\n",
+                                others.len()
+                            ));
+                        }
+                    }
+                    Err(e) => output.push_str(&format!("failed to save cache {key}. {e}\n")),
+                }
+            }
+        }
+
+        if let Some(matrix) = &job.pipeline.matrix {
+            details.push(matrix_table(matrix, &results));
+        }
+
+        if let Some(patterns) = &job.pipeline.artifacts {
+            match artifacts::collect(run_id, &job.name, Path::new(CACHE_DIR), patterns) {
+                Ok((artifacts, unmatched)) => {
+                    for pattern in unmatched {
+                        output.push_str(&format!("artifact {pattern:?} matched no files\n"));
+                    }
+
+                    collected.extend(artifacts);
+                }
+                Err(e) => {
+                    eprintln!("failed to collect artifacts of {}. {e}", job.name);
+                    details.push(format!("failed to collect artifacts of {}. {e}", job.name));
+                }
+            }
+        }
+
+        if let (Some(environment), Outcome::Passed) = (&job.pipeline.environment, outcome) {
+            let deployment = Deployment {
+                run_id,
+                job: job.name.clone(),
+                commit_sha: commit_sha.clone(),
+                branch: branch.clone(),
+                triggered_by: triggered_by.clone(),
+                deployed_at: started_at,
+                rolled_back: false,
+            };
+
+            match environments::record(&repo.repo_name, environment, deployment) {
+                Ok(()) => output.push_str(&format!("=== deployed to {environment}\n")),
+                Err(e) => {
+                    eprintln!("failed to record the deployment to {environment}. {e}");
+                    output.push_str(&format!(
+                        "failed to record the deployment to {environment}. {e}\n"
+                    ));
+                }
+            }
+        }
+
+        let (reason, approval) = approved.unzip();
+        let outputs = results
+            .iter()
+            .flat_map(|cell| cell.steps.iter())
+            .flat_map(|step| step.outputs.clone())
+            .collect();
+        job_results.push(JobRecord {
+            name: job.name.clone(),
+            outcome,
+            reason,
+            cells: results,
+            approval,
+            outputs,
+        });
+    }
+
+    services.teardown(&runners.launcher);
+
+    {
+        let mut l = logs.lock().await;
+        *l = mask(&output, &secrets);
+    }
+
+    let outcome = Outcome::combine(job_results.iter().map(|res| res.outcome));
+    let mut msg = match outcome {
+        Outcome::Passed => {
+            format!("pipline run {run_id} completed sucessfully. use `/logs` to view logs.")
+        }
+        Outcome::Error => format!("pipline run {run_id} errored! use `/logs` to view logs."),
+        _ => format!("pipline run {run_id} failed! use `/logs to view logs."),
+    };
+
+    for job in job_results.iter() {
+        let indent = if jobs.len() > 1 {
+            msg.push_str(&format!("\n- {}: {}", job.name, job.outcome));
+            "  "
+        } else {
+            ""
+        };
+
+        // matrix jobs get a table instead.
+        if let [cell] = job.cells.as_slice() {
+            if cell.matrix.is_empty() {
+                for step in cell.steps.iter() {
+                    msg.push_str(&format!("\n{indent}- {}", step.summary()));
+                }
+            }
+        }
+
+        for cell in job.cells.iter() {
+            match &cell.reason {
+                Some(reason) if cell.matrix.is_empty() => {
+                    msg.push_str(&format!("\n{indent}{reason}"))
+                }
+                Some(reason) => msg.push_str(&format!(
+                    "\n{indent}{}: {reason}",
+                    matrix_label(&cell.matrix)
+                )),
+                None => {}
+            }
+
+            if !cell.retries.is_empty() {
+                let label = if cell.matrix.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}: ", matrix_label(&cell.matrix))
+                };
+                msg.push_str(&format!(
+                    "\n{indent}{label}{} after {} attempts.",
+                    cell.outcome,
+                    cell.retries.len() + 1
+                ));
+            }
         }
+
+        if let Some(reason) = &job.reason {
+            msg.push_str(&format!("\n{indent}{reason}"));
+        }
+
+        if !job.outputs.is_empty() {
+            msg.push_str(&format!("\n{indent}outputs: {}", fmt_outputs(&job.outputs)));
+        }
+    }
+
+    for detail in details {
+        msg.push_str(&format!("\n{detail}"));
+    }
+
+    let (small, large): (Vec<_>, Vec<_>) = collected
+        .iter()
+        .partition(|artifact| artifact.size <= ATTACH_LIMIT);
+    let attachments = small
+        .iter()
+        .take(MAX_ATTACHMENTS)
+        .map(|artifact| artifacts::run_dir(run_id).join(artifact.stored_path()))
+        .collect();
+
+    if !large.is_empty() || small.len() > MAX_ATTACHMENTS {
+        msg.push_str(&format!(
+            "\n{} artifacts collected. use `/artifacts {run_id}` to download them.",
+            collected.len()
+        ));
+    }
+
+    if jobs.iter().any(|job| job.pipeline.services.is_some()) {
+        msg.push_str(&format!(
+            "\nuse `/service_logs {run_id} <service>` to view service logs."
+        ));
     }
 
+    let record = RunRecord {
+        run_id,
+        repo: repo.repo_name.clone(),
+        commit_sha,
+        branch,
+        pipeline: pipeline_name,
+        triggered_by,
+        event: Some(event),
+        rerun_of,
+        inputs,
+        started_at,
+        duration_ms: started.elapsed().as_millis() as u64,
+        outcome,
+        // outputs are passed on unmasked, only what's kept is masked.
+        jobs: job_results
+            .into_iter()
+            .map(|job| job.masked(&secrets))
+            .collect(),
+    };
+
+    if let Err(e) = history::save(&record) {
+        eprintln!("failed to save run {run_id} to the history. {e}");
+    }
+
+    on_complete(RunReport {
+        msg,
+        attachments,
+        run_id: Some(run_id),
+        outcome: Some(outcome),
+        summary: summary::collect(&record.jobs),
+    });
+
     println!("run done");
 
     {
@@ -299,5 +2275,229 @@ pub async fn run_backend(input: Receiver<CiCdCmd>, backend: Arc<Mutex<Backend>>)
                 eprintln!("{e}");
             }
         }
+
+        // only pick up queued runs once user commands have drained so a queued clone can't
+        // land between a `/run`'s clone and its pipeline.
+        if input.is_empty() && !backend.is_busy().await {
+            if let Some(queued) = backend.queue.pop_front() {
+                if let Err(e) = backend.start_queued(queued).await {
+                    eprintln!("{e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn interpolates_known_vars() {
+        let vars = env(&[("NAME", "app"), ("TAG", "1.2"), ("EMPTY", "")]);
+
+        assert_eq!(interpolate("${NAME}:${TAG}", &vars), "app:1.2");
+        assert_eq!(interpolate("[${EMPTY}]", &vars), "[]");
+        assert_eq!(interpolate("no vars, $NAME", &vars), "no vars, $NAME");
+
+        // values aren't expanded again.
+        let vars = env(&[("A", "${B}"), ("B", "b")]);
+        assert_eq!(interpolate("${A}", &vars), "${B}");
+    }
+
+    #[test]
+    fn leaves_unknown_vars_for_the_shell() {
+        let vars = env(&[("NAME", "app")]);
+
+        assert_eq!(interpolate("${HOME}/${NAME} ${}", &vars), "${HOME}/app ${}");
+        assert_eq!(interpolate("${NAME${NAME}}", &vars), "${NAME${NAME}}");
+    }
+
+    #[test]
+    fn escapes_vars() {
+        let vars = env(&[("NAME", "app")]);
+
+        assert_eq!(interpolate("$${NAME} ${NAME}", &vars), "${NAME} app");
+    }
+
+    #[test]
+    fn leaves_unterminated_vars() {
+        let vars = env(&[("NAME", "app")]);
+
+        assert_eq!(interpolate("${NAME", &vars), "${NAME");
+        assert_eq!(interpolate("${NAME} ${NAME", &vars), "app ${NAME");
+        assert_eq!(interpolate("$", &vars), "$");
+        assert_eq!(interpolate("${", &vars), "${");
+    }
+
+    fn matrix(json: serde_json::Value) -> Matrix {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn matrix_cells_vary_the_last_name_fastest() {
+        let matrix = matrix(serde_json::json!({
+            "os": ["linux", "mac"],
+            "rust": ["stable", "nightly"],
+        }));
+
+        assert_eq!(
+            matrix.cells(),
+            [
+                env(&[("os", "linux"), ("rust", "stable")]),
+                env(&[("os", "linux"), ("rust", "nightly")]),
+                env(&[("os", "mac"), ("rust", "stable")]),
+                env(&[("os", "mac"), ("rust", "nightly")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn matrix_cells_are_ordered_by_name_not_as_written() {
+        let matrix = matrix(serde_json::json!({
+            "z": ["1", "2"],
+            "a": ["x", "y"],
+        }));
+
+        assert_eq!(
+            matrix.cells(),
+            [
+                env(&[("a", "x"), ("z", "1")]),
+                env(&[("a", "x"), ("z", "2")]),
+                env(&[("a", "y"), ("z", "1")]),
+                env(&[("a", "y"), ("z", "2")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn matrix_excludes_every_matching_cell() {
+        let matrix = matrix(serde_json::json!({
+            "os": ["linux", "mac", "windows"],
+            "rust": ["stable", "nightly"],
+            "exclude": [
+                {"os": "windows"},
+                {"os": "mac", "rust": "nightly"},
+                // matches nothing.
+                {"os": "bsd"},
+            ],
+        }));
+
+        assert_eq!(
+            matrix.cells(),
+            [
+                env(&[("os", "linux"), ("rust", "stable")]),
+                env(&[("os", "linux"), ("rust", "nightly")]),
+                env(&[("os", "mac"), ("rust", "stable")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn matrix_includes_come_last() {
+        let matrix = matrix(serde_json::json!({
+            "os": ["linux", "mac"],
+            "exclude": [{"os": "mac"}],
+            "include": [
+                // already a cell.
+                {"os": "linux"},
+                // excludes don't apply to includes.
+                {"os": "mac"},
+                {"os": "linux", "target": "musl"},
+            ],
+        }));
+
+        assert_eq!(
+            matrix.cells(),
+            [
+                env(&[("os", "linux")]),
+                env(&[("os", "mac")]),
+                env(&[("os", "linux"), ("target", "musl")]),
+            ]
+        );
+        assert_eq!(matrix.names(), ["os", "target"]);
+    }
+
+    #[test]
+    fn matrix_without_vars() {
+        assert_eq!(matrix(serde_json::json!({})).cells(), [Env::new()]);
+        assert_eq!(
+            matrix(serde_json::json!({"include": [{"os": "linux"}, {"os": "mac"}]})).cells(),
+            [env(&[("os", "linux")]), env(&[("os", "mac")])]
+        );
+        // a name without values leaves nothing to run.
+        assert!(matrix(serde_json::json!({"os": []})).cells().is_empty());
+    }
+
+    fn retry(backoff: Option<u64>) -> Retry {
+        Retry {
+            max_attempts: 3,
+            backoff,
+            on: None,
+            exit_codes: None,
+        }
+    }
+
+    #[test]
+    fn retries_until_max_attempts() {
+        let retry = retry(None);
+
+        assert!(retry.should_retry(1, FailureKind::ExitCode, Some(1)));
+        assert!(retry.should_retry(2, FailureKind::Timeout, None));
+        assert!(!retry.should_retry(3, FailureKind::ExitCode, Some(1)));
+        assert!(!retry.should_retry(4, FailureKind::Error, None));
+    }
+
+    #[test]
+    fn only_retries_listed_failures() {
+        let retry = Retry {
+            on: Some(vec![FailureKind::Timeout, FailureKind::ExitCode]),
+            exit_codes: Some(vec![137]),
+            ..retry(None)
+        };
+
+        assert!(retry.should_retry(1, FailureKind::Timeout, None));
+        assert!(retry.should_retry(1, FailureKind::ExitCode, Some(137)));
+        assert!(!retry.should_retry(1, FailureKind::ExitCode, Some(1)));
+        // an exit code failure without a code can't match the list.
+        assert!(!retry.should_retry(1, FailureKind::ExitCode, None));
+        assert!(!retry.should_retry(1, FailureKind::Error, None));
+    }
+
+    #[test]
+    fn exit_codes_only_limit_exit_code_failures() {
+        let retry = Retry {
+            exit_codes: Some(vec![2]),
+            ..retry(None)
+        };
+
+        assert!(retry.should_retry(1, FailureKind::Timeout, None));
+        assert!(retry.should_retry(1, FailureKind::Error, None));
+        assert!(!retry.should_retry(1, FailureKind::ExitCode, Some(1)));
+    }
+
+    #[test]
+    fn delay_doubles() {
+        assert_eq!(retry(Some(5)).delay(1), Duration::from_secs(5));
+        assert_eq!(retry(Some(5)).delay(2), Duration::from_secs(10));
+        assert_eq!(retry(Some(5)).delay(3), Duration::from_secs(20));
+        assert_eq!(retry(Some(5)).delay(0), Duration::from_secs(5));
+        assert_eq!(retry(None).delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        assert_eq!(retry(Some(60)).delay(6), Duration::from_secs(1920));
+        assert_eq!(retry(Some(60)).delay(7), MAX_RETRY_DELAY);
+        assert_eq!(retry(Some(1)).delay(u32::MAX), MAX_RETRY_DELAY);
+        // the doubling stops at 2^16 and the multiplication saturates instead of overflowing.
+        assert_eq!(retry(Some(u64::MAX)).delay(20), MAX_RETRY_DELAY);
     }
 }
@@ -0,0 +1,690 @@
+use serde_json::{Map, Number, Value as Json};
+use std::{collections::HashMap, path::Path};
+use toml_edit::{ImDocument, Item, Table, Value};
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::{Marker, TScalarStyle},
+    Yaml,
+};
+
+/// what a pipeline file is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// the format of a file by its extension. anything unknown is read as toml.
+    pub fn of(file: impl AsRef<Path>) -> Self {
+        match file.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("json") => Format::Json,
+            _ => Format::Toml,
+        }
+    }
+}
+
+/// a table, array or value of a pipeline file, with where it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    /// byte offset into the file.
+    pub at: Option<usize>,
+    pub value: NodeValue,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeValue {
+    /// keys with where they start, in file order.
+    Table(Vec<(String, Option<usize>, Node)>),
+    Array(Vec<Node>),
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    /// only in toml.
+    Datetime(String),
+    /// only in yaml and json.
+    Null,
+}
+
+/// why a file couldn't be parsed at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// byte offset into the file.
+    pub at: usize,
+    pub message: String,
+}
+
+impl Node {
+    fn new(at: Option<usize>, value: NodeValue) -> Self {
+        Self { at, value }
+    }
+
+    /// the json type the node would be.
+    pub fn kind(&self) -> &'static str {
+        match self.value {
+            NodeValue::Table(_) => "object",
+            NodeValue::Array(_) => "array",
+            NodeValue::String(_) => "string",
+            NodeValue::Integer(_) => "integer",
+            NodeValue::Float(_) => "number",
+            NodeValue::Boolean(_) => "boolean",
+            NodeValue::Datetime(_) => "datetime",
+            NodeValue::Null => "null",
+        }
+    }
+
+    pub fn entries(&self) -> &[(String, Option<usize>, Node)] {
+        match &self.value {
+            NodeValue::Table(entries) => entries,
+            _ => &[],
+        }
+    }
+
+    pub fn elements(&self) -> &[Node] {
+        match &self.value {
+            NodeValue::Array(elements) => elements,
+            _ => &[],
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        self.entries()
+            .iter()
+            .find(|(name, _, _)| name == key)
+            .map(|(_, _, node)| node)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            NodeValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self.value {
+            NodeValue::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    /// moves every offset by `base`, for when the file is one of several.
+    pub fn shift(&mut self, base: usize) {
+        self.at = self.at.map(|at| at + base);
+
+        match &mut self.value {
+            NodeValue::Table(entries) => {
+                for (_, at, node) in entries {
+                    *at = at.map(|at| at + base);
+                    node.shift(base);
+                }
+            }
+            NodeValue::Array(elements) => {
+                for node in elements {
+                    node.shift(base);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// copies the keys of `other` this table doesn't set. the tables under `merged` are combined
+    /// key by key instead, again keeping this tables keys.
+    pub fn merge(&mut self, other: &Node, merged: &[&str]) {
+        let NodeValue::Table(entries) = &mut self.value else {
+            return;
+        };
+
+        for (key, at, val) in other.entries() {
+            match entries.iter_mut().find(|(name, _, _)| name == key) {
+                Some((_, _, node)) if merged.contains(&key.as_str()) => node.merge(val, &[]),
+                Some(_) => {}
+                None => entries.push((key.clone(), *at, val.clone())),
+            }
+        }
+    }
+
+    /// the node as json, for deserializing the formats toml can't.
+    pub fn to_json(&self) -> Json {
+        match &self.value {
+            NodeValue::Table(entries) => Json::Object(
+                entries
+                    .iter()
+                    .map(|(key, _, node)| (key.clone(), node.to_json()))
+                    .collect::<Map<_, _>>(),
+            ),
+            NodeValue::Array(elements) => Json::Array(elements.iter().map(Node::to_json).collect()),
+            NodeValue::String(s) | NodeValue::Datetime(s) => Json::String(s.clone()),
+            NodeValue::Integer(i) => Json::Number((*i).into()),
+            NodeValue::Float(f) => Number::from_f64(*f).map_or(Json::Null, Json::Number),
+            NodeValue::Boolean(b) => Json::Bool(*b),
+            NodeValue::Null => Json::Null,
+        }
+    }
+}
+
+/// parses a pipeline file into a tree. json is parsed as the yaml it also is, so both keep
+/// where everything is.
+pub fn parse(source: &str, format: Format) -> Result<Node, SyntaxError> {
+    match format {
+        Format::Toml => parse_toml(source),
+        Format::Yaml | Format::Json => parse_yaml(source),
+    }
+}
+
+fn parse_toml(source: &str) -> Result<Node, SyntaxError> {
+    fn table(t: &Table) -> Node {
+        Node::new(
+            t.span().map(|span| span.start),
+            NodeValue::Table(
+                t.iter()
+                    .map(|(key, it)| {
+                        let at = t.key(key).and_then(|key| key.span());
+                        (key.to_string(), at.map(|span| span.start), item(it))
+                    })
+                    .collect(),
+            ),
+        )
+    }
+
+    fn item(item: &Item) -> Node {
+        match item {
+            Item::Value(val) => value(val),
+            Item::Table(t) => table(t),
+            Item::ArrayOfTables(tables) => Node::new(
+                tables.span().map(|span| span.start),
+                NodeValue::Array(tables.iter().map(table).collect()),
+            ),
+            Item::None => Node::new(None, NodeValue::Null),
+        }
+    }
+
+    fn value(val: &Value) -> Node {
+        let at = val.span().map(|span| span.start);
+
+        Node::new(
+            at,
+            match val {
+                Value::String(s) => NodeValue::String(s.value().clone()),
+                Value::Integer(i) => NodeValue::Integer(*i.value()),
+                Value::Float(f) => NodeValue::Float(*f.value()),
+                Value::Boolean(b) => NodeValue::Boolean(*b.value()),
+                Value::Datetime(dt) => NodeValue::Datetime(dt.value().to_string()),
+                Value::Array(array) => NodeValue::Array(array.iter().map(value).collect()),
+                Value::InlineTable(t) => NodeValue::Table(
+                    t.iter()
+                        .map(|(key, val)| {
+                            let at = t.key(key).and_then(|key| key.span());
+                            (key.to_string(), at.map(|span| span.start), value(val))
+                        })
+                        .collect(),
+                ),
+            },
+        )
+    }
+
+    match ImDocument::parse(source) {
+        Ok(doc) => Ok(table(doc.as_table())),
+        Err(e) => Err(SyntaxError {
+            at: e.span().map(|span| span.start).unwrap_or(0),
+            message: e.message().trim_end().replace('\n', ", "),
+        }),
+    }
+}
+
+/// an open sequence or mapping.
+struct Frame {
+    node: Node,
+    anchor: usize,
+    /// a mapping's key waiting for its value.
+    key: Option<(String, Option<usize>)>,
+}
+
+/// builds the tree from the yaml parsers events.
+struct YamlBuilder {
+    /// byte offset of each char, yaml marks are in chars.
+    offsets: Vec<usize>,
+    stack: Vec<Frame>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+    error: Option<SyntaxError>,
+}
+
+impl YamlBuilder {
+    fn offset(&self, mark: Marker) -> usize {
+        self.offsets
+            .get(mark.index())
+            .copied()
+            .unwrap_or(self.offsets.last().copied().unwrap_or(0))
+    }
+
+    fn fail(&mut self, at: Option<usize>, message: String) {
+        if self.error.is_none() {
+            self.error = Some(SyntaxError {
+                at: at.unwrap_or(0),
+                message,
+            });
+        }
+    }
+
+    /// adds a finished node to whatever is open.
+    fn push(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+
+        let Some(frame) = self.stack.last_mut() else {
+            self.root = Some(node);
+            return;
+        };
+
+        match (&mut frame.node.value, frame.key.take()) {
+            (NodeValue::Array(elements), _) => elements.push(node),
+            (NodeValue::Table(entries), Some((key, at))) => {
+                if entries.iter().any(|(name, _, _)| *name == key) {
+                    self.fail(at, format!("duplicate key `{key}`"));
+                } else {
+                    entries.push((key, at, node));
+                }
+            }
+            (NodeValue::Table(_), None) => {
+                let key = match node.value {
+                    NodeValue::String(s) => s,
+                    NodeValue::Integer(i) => i.to_string(),
+                    NodeValue::Float(f) => f.to_string(),
+                    NodeValue::Boolean(b) => b.to_string(),
+                    _ => {
+                        self.fail(node.at, "keys must be strings".into());
+                        return;
+                    }
+                };
+                frame.key = Some((key, node.at));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for YamlBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let at = Some(self.offset(mark));
+
+        match ev {
+            Event::Scalar(val, style, anchor, _) => {
+                let value = match style {
+                    TScalarStyle::Plain => match Yaml::from_str(&val) {
+                        Yaml::Integer(i) => NodeValue::Integer(i),
+                        Yaml::Real(f) => f
+                            .parse()
+                            .map(NodeValue::Float)
+                            .unwrap_or(NodeValue::String(val)),
+                        Yaml::Boolean(b) => NodeValue::Boolean(b),
+                        Yaml::Null => NodeValue::Null,
+                        _ => NodeValue::String(val),
+                    },
+                    _ => NodeValue::String(val),
+                };
+                self.push(Node::new(at, value), anchor);
+            }
+            Event::SequenceStart(anchor, _) => self.stack.push(Frame {
+                node: Node::new(at, NodeValue::Array(Vec::new())),
+                anchor,
+                key: None,
+            }),
+            Event::MappingStart(anchor, _) => self.stack.push(Frame {
+                node: Node::new(at, NodeValue::Table(Vec::new())),
+                anchor,
+                key: None,
+            }),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some(mut frame) = self.stack.pop() {
+                    // block mappings are marked at the `:` of their first key, not where they
+                    // start.
+                    if let Some(first) = frame.node.entries().first().and_then(|(_, at, _)| *at) {
+                        frame.node.at = frame.node.at.map(|at| at.min(first));
+                    }
+
+                    merge_keys(&mut frame.node);
+                    self.push(frame.node, frame.anchor);
+                }
+            }
+            Event::Alias(anchor) => match self.anchors.get(&anchor).cloned() {
+                Some(node) => self.push(Node { at, ..node }, 0),
+                None => self.fail(at, "alias to an unknown anchor".into()),
+            },
+            _ => {}
+        }
+    }
+}
+
+/// expands yaml merge keys, `<<: *defaults`. keys set in the mapping itself win.
+fn merge_keys(node: &mut Node) {
+    let NodeValue::Table(entries) = &mut node.value else {
+        return;
+    };
+
+    let Some(i) = entries.iter().position(|(key, _, _)| key == "<<") else {
+        return;
+    };
+
+    let (_, _, merged) = entries.remove(i);
+    let merged = match merged.value {
+        NodeValue::Array(tables) => tables,
+        _ => vec![merged],
+    };
+
+    for (key, at, val) in merged.iter().flat_map(|table| table.entries()) {
+        if !entries.iter().any(|(name, _, _)| name == key) {
+            entries.push((key.clone(), *at, val.clone()));
+        }
+    }
+}
+
+fn parse_yaml(source: &str) -> Result<Node, SyntaxError> {
+    let mut builder = YamlBuilder {
+        offsets: source
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([source.len()])
+            .collect(),
+        stack: Vec::new(),
+        anchors: HashMap::new(),
+        root: None,
+        error: None,
+    };
+
+    if let Err(e) = Parser::new_from_str(source).load(&mut builder, false) {
+        let at = builder.offset(*e.marker());
+        return Err(SyntaxError {
+            at,
+            message: e.info().to_string(),
+        });
+    }
+
+    if let Some(e) = builder.error {
+        return Err(e);
+    }
+
+    // an empty file has no pipelines.
+    Ok(builder
+        .root
+        .filter(|root| root.value != NodeValue::Null)
+        .unwrap_or(Node::new(Some(0), NodeValue::Table(Vec::new()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TOML: &str = r#"[build]
+container = "rust:1.80"
+script = ["cargo build", { run = "cargo test", timeout = 60 }]
+env = { RUST_LOG = "debug" }
+
+[build.retry]
+max_attempts = 2
+"#;
+
+    const YAML: &str = r#"build:
+  container: "rust:1.80"
+  script:
+    - cargo build
+    - run: cargo test
+      timeout: 60
+  env:
+    RUST_LOG: debug
+  retry:
+    max_attempts: 2
+"#;
+
+    const JSON: &str = r#"{
+  "build": {
+    "container": "rust:1.80",
+    "script": ["cargo build", {"run": "cargo test", "timeout": 60}],
+    "env": {"RUST_LOG": "debug"},
+    "retry": {"max_attempts": 2}
+  }
+}
+"#;
+
+    /// the source from where a node starts.
+    fn from(source: &str, at: Option<usize>) -> &str {
+        &source[at.expect("the node has no offset")..]
+    }
+
+    fn key_at(node: &Node, key: &str) -> Option<usize> {
+        node.entries()
+            .iter()
+            .find(|(name, _, _)| name == key)
+            .and_then(|(_, at, _)| *at)
+    }
+
+    #[test]
+    fn formats_read_the_same() {
+        let expected = json!({
+            "build": {
+                "container": "rust:1.80",
+                "script": ["cargo build", {"run": "cargo test", "timeout": 60}],
+                "env": {"RUST_LOG": "debug"},
+                "retry": {"max_attempts": 2},
+            }
+        });
+
+        assert_eq!(parse(TOML, Format::Toml).unwrap().to_json(), expected);
+        assert_eq!(parse(YAML, Format::Yaml).unwrap().to_json(), expected);
+        assert_eq!(parse(JSON, Format::Json).unwrap().to_json(), expected);
+    }
+
+    #[test]
+    fn offsets_point_at_what_they_belong_to() {
+        for (source, format) in [
+            (TOML, Format::Toml),
+            (YAML, Format::Yaml),
+            (JSON, Format::Json),
+        ] {
+            let root = parse(source, format).unwrap();
+            let build = root.get("build").unwrap();
+            let script = build.get("script").unwrap();
+            let step = &script.elements()[1];
+            let retry = build.get("retry").unwrap();
+
+            let quote = if format == Format::Json { "\"" } else { "" };
+            let key = |key: &str| format!("{quote}{key}{quote}");
+            let string = |s: &str| match format {
+                Format::Yaml => s.to_string(),
+                _ => format!("\"{s}\""),
+            };
+
+            assert!(from(source, key_at(&root, "build")).starts_with(&key("build")));
+            assert!(from(source, key_at(build, "container")).starts_with(&key("container")));
+            assert!(from(source, build.get("container").unwrap().at).starts_with("\"rust:1.80\""));
+            assert!(from(source, script.elements()[0].at).starts_with(&string("cargo build")));
+            assert!(from(source, key_at(step, "timeout")).starts_with(&key("timeout")));
+            assert!(from(source, step.get("timeout").unwrap().at).starts_with("60"));
+            assert!(from(source, key_at(retry, "max_attempts")).starts_with(&key("max_attempts")));
+            assert!(from(source, retry.get("max_attempts").unwrap().at).starts_with("2"));
+        }
+    }
+
+    #[test]
+    fn tables_start_where_they_are_written() {
+        let toml = parse(TOML, Format::Toml).unwrap();
+        let build = toml.get("build").unwrap();
+        assert!(from(TOML, build.get("script").unwrap().elements()[1].at).starts_with("{ run"));
+        assert!(from(TOML, build.get("env").unwrap().at).starts_with("{ RUST_LOG"));
+
+        let yaml = parse(YAML, Format::Yaml).unwrap();
+        let build = yaml.get("build").unwrap();
+        assert_eq!(yaml.at, Some(0));
+        assert!(from(YAML, build.at).starts_with("container:"));
+        assert!(from(YAML, build.get("script").unwrap().at).starts_with("- cargo build"));
+        // a block mapping in a sequence starts at its first key.
+        assert!(from(YAML, build.get("script").unwrap().elements()[1].at)
+            .starts_with("run: cargo test"));
+        assert!(from(YAML, build.get("retry").unwrap().at).starts_with("max_attempts:"));
+
+        let json = parse(JSON, Format::Json).unwrap();
+        let build = json.get("build").unwrap();
+        assert!(from(JSON, build.at).starts_with("{\n    \"container\""));
+        assert!(from(JSON, build.get("script").unwrap().elements()[1].at).starts_with("{\"run\""));
+    }
+
+    #[test]
+    fn offsets_are_in_bytes() {
+        let yaml = "name: \"héllo wörld\"\ncontainer: alpine\n";
+        let root = parse(yaml, Format::Yaml).unwrap();
+        assert!(from(yaml, key_at(&root, "container")).starts_with("container"));
+        assert!(from(yaml, root.get("container").unwrap().at).starts_with("alpine"));
+
+        let toml = "name = \"héllo wörld\"\ncontainer = \"alpine\"\n";
+        let root = parse(toml, Format::Toml).unwrap();
+        assert!(from(toml, key_at(&root, "container")).starts_with("container"));
+        assert!(from(toml, root.get("container").unwrap().at).starts_with("\"alpine\""));
+    }
+
+    #[test]
+    fn shift_moves_every_offset() {
+        let mut shifted = parse(YAML, Format::Yaml).unwrap();
+        shifted.shift(100);
+        let root = parse(YAML, Format::Yaml).unwrap();
+
+        assert_eq!(shifted.at, root.at.map(|at| at + 100));
+        assert_eq!(
+            key_at(&shifted, "build"),
+            key_at(&root, "build").map(|at| at + 100)
+        );
+
+        let step = |root: &Node| {
+            let step = root.get("build").unwrap().get("script").unwrap().elements()[1].clone();
+            (
+                step.at,
+                key_at(&step, "timeout"),
+                step.get("timeout").unwrap().at,
+            )
+        };
+        let (at, key, value) = step(&root);
+        assert_eq!(
+            step(&shifted),
+            (
+                at.map(|at| at + 100),
+                key.map(|at| at + 100),
+                value.map(|at| at + 100)
+            )
+        );
+        assert_eq!(shifted.to_json(), root.to_json());
+    }
+
+    #[test]
+    fn merge_keeps_own_keys() {
+        let mut node = parse("a: 1\nenv:\n  X: own\n", Format::Yaml).unwrap();
+        let other = parse("a: 2\nb: 3\nenv:\n  X: other\n  Y: other\n", Format::Yaml).unwrap();
+
+        let mut plain = node.clone();
+        plain.merge(&other, &[]);
+        assert_eq!(
+            plain.to_json(),
+            json!({"a": 1, "env": {"X": "own"}, "b": 3})
+        );
+
+        node.merge(&other, &["env"]);
+        assert_eq!(
+            node.to_json(),
+            json!({"a": 1, "env": {"X": "own", "Y": "other"}, "b": 3})
+        );
+    }
+
+    #[test]
+    fn yaml_anchors_and_merge_keys() {
+        let yaml = "base: &base\n  container: alpine\n  env: {A: a}\nbuild:\n  <<: *base\n  env: {B: b}\ncopy: *base\n";
+        let root = parse(yaml, Format::Yaml).unwrap();
+
+        assert_eq!(
+            root.to_json(),
+            json!({
+                "base": {"container": "alpine", "env": {"A": "a"}},
+                "build": {"env": {"B": "b"}, "container": "alpine"},
+                "copy": {"container": "alpine", "env": {"A": "a"}},
+            })
+        );
+        // an alias is where it's used.
+        assert!(from(yaml, root.get("copy").unwrap().at).starts_with("*base"));
+    }
+
+    #[test]
+    fn scalars() {
+        let yaml = "s: text\nq: '1'\ni: 1\nf: 1.5\nb: true\nn: null\n";
+        let toml = "s = \"text\"\nq = \"1\"\ni = 1\nf = 1.5\nb = true\nd = 1979-05-27\n";
+
+        let yaml = parse(yaml, Format::Yaml).unwrap();
+        let kinds: Vec<&str> = yaml
+            .entries()
+            .iter()
+            .map(|(_, _, node)| node.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            ["string", "string", "integer", "number", "boolean", "null"]
+        );
+
+        let toml = parse(toml, Format::Toml).unwrap();
+        let kinds: Vec<&str> = toml
+            .entries()
+            .iter()
+            .map(|(_, _, node)| node.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            ["string", "string", "integer", "number", "boolean", "datetime"]
+        );
+        assert_eq!(toml.get("d").unwrap().to_json(), json!("1979-05-27"));
+    }
+
+    #[test]
+    fn empty_files_have_no_pipelines() {
+        assert_eq!(parse("", Format::Yaml).unwrap().to_json(), json!({}));
+        assert_eq!(
+            parse("# nothing\n", Format::Yaml).unwrap().to_json(),
+            json!({})
+        );
+        assert_eq!(parse("", Format::Toml).unwrap().to_json(), json!({}));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let yaml = "a: 1\na: 2\n";
+        let e = parse(yaml, Format::Yaml).unwrap_err();
+        assert_eq!(e.message, "duplicate key `a`");
+        assert_eq!(&yaml[e.at..], "a: 2\n");
+
+        let yaml = "a: *nope\n";
+        let e = parse(yaml, Format::Yaml).unwrap_err();
+        assert_eq!(e.message, "while parsing node, found unknown anchor");
+        assert_eq!(&yaml[e.at..], "*nope\n");
+
+        let yaml = "a:\n  b: c\n d: e\n";
+        let e = parse(yaml, Format::Yaml).unwrap_err();
+        assert_eq!(
+            e.message,
+            "while parsing a block mapping, did not find expected key"
+        );
+        assert_eq!(&yaml[e.at..], ": e\n");
+
+        let toml = "[a]\nb = [\"c\"\n";
+        let e = parse(toml, Format::Toml).unwrap_err();
+        assert_eq!(e.message, "invalid array, expected `]`");
+        assert_eq!(e.at, toml.len());
+    }
+
+    #[test]
+    fn format_of() {
+        assert_eq!(Format::of(".dcicd.toml"), Format::Toml);
+        assert_eq!(Format::of(".dcicd.yaml"), Format::Yaml);
+        assert_eq!(Format::of("ci/shared.yml"), Format::Yaml);
+        assert_eq!(Format::of(".dcicd.json"), Format::Json);
+        assert_eq!(Format::of("pipelines"), Format::Toml);
+    }
+}
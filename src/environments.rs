@@ -0,0 +1,152 @@
+use crate::{
+    approval::Approval,
+    ci_cd::{PipelineName, RepoName, RunId, STATE_DIR},
+};
+use anyhow::Result;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read_to_string, write},
+    path::PathBuf,
+};
+
+pub type EnvironmentName = String;
+
+pub const ENVIRONMENTS_FILE: &str = "environments.json";
+
+/// rules for deploying to an environment. they're set from discord, so the repo being deployed
+/// can't loosen them.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Protection {
+    /// branches allowed to deploy, globs like `release/*` work. any branch can if empty.
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// someone has to approve every deployment first.
+    pub approval: Option<Approval>,
+}
+
+impl Protection {
+    pub fn allows_branch(&self, branch: &str) -> bool {
+        self.branches.is_empty()
+            || self.branches.iter().any(|allowed| {
+                allowed == branch
+                    || Pattern::new(allowed).is_ok_and(|pattern| pattern.matches(branch))
+            })
+    }
+}
+
+/// a passed run of a pipeline deploying to an environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deployment {
+    pub run_id: RunId,
+    /// the pipeline that deployed.
+    pub job: PipelineName,
+    pub commit_sha: String,
+    pub branch: String,
+    pub triggered_by: String,
+    /// unix time.
+    pub deployed_at: u64,
+    /// a rollback replaced it, so it's never rolled back to.
+    #[serde(default)]
+    pub rolled_back: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Environment {
+    #[serde(default)]
+    pub protection: Protection,
+    /// oldest first.
+    #[serde(default)]
+    pub deployments: Vec<Deployment>,
+}
+
+impl Environment {
+    /// what's deployed at the moment.
+    pub fn current(&self) -> Option<&Deployment> {
+        self.deployments.last()
+    }
+
+    /// what a rollback goes back to. the latest earlier deployment of another commit that wasn't
+    /// rolled back itself.
+    pub fn rollback_target(&self) -> Option<&Deployment> {
+        let (current, earlier) = self.deployments.split_last()?;
+
+        earlier.iter().rev().find(|deployment| {
+            !deployment.rolled_back && deployment.commit_sha != current.commit_sha
+        })
+    }
+}
+
+/// the environments of every repo, kept in `STATE_DIR/environments.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Environments {
+    pub repos: BTreeMap<RepoName, BTreeMap<EnvironmentName, Environment>>,
+}
+
+impl Environments {
+    fn path() -> PathBuf {
+        let mut path = PathBuf::from(STATE_DIR);
+        path.push(ENVIRONMENTS_FILE);
+
+        path
+    }
+
+    pub fn load() -> Result<Self> {
+        match read_to_string(Self::path()) {
+            Ok(environments) => Ok(serde_json::from_str(&environments)?),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        create_dir_all(STATE_DIR)?;
+        write(Self::path(), serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn get(&self, repo: &str, name: &str) -> Option<&Environment> {
+        self.repos.get(repo)?.get(name)
+    }
+
+    /// the environment, created without protection if it's new.
+    pub fn get_mut(&mut self, repo: &str, name: &str) -> &mut Environment {
+        self.repos
+            .entry(repo.to_string())
+            .or_default()
+            .entry(name.to_string())
+            .or_default()
+    }
+
+    /// the rules of an environment. unknown environments have none.
+    pub fn protection(&self, repo: &str, name: &str) -> Protection {
+        self.get(repo, name)
+            .map(|environment| environment.protection.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// records a deployment as the current one of its environment.
+pub fn record(repo: &str, name: &str, deployment: Deployment) -> Result<()> {
+    let mut environments = Environments::load()?;
+    environments
+        .get_mut(repo, name)
+        .deployments
+        .push(deployment);
+
+    environments.save()
+}
+
+/// marks the deployment of run `run_id` as rolled back, once a rollback replaced it.
+pub fn mark_rolled_back(repo: &str, name: &str, run_id: RunId) -> Result<()> {
+    let mut environments = Environments::load()?;
+
+    for deployment in environments.get_mut(repo, name).deployments.iter_mut() {
+        if deployment.run_id == run_id {
+            deployment.rolled_back = true;
+        }
+    }
+
+    environments.save()
+}
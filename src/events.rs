@@ -0,0 +1,465 @@
+use crate::{
+    ci_cd::{Env, Outcome},
+    outputs::fmt_outputs,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// marks the lines of the runners stdout that are events. anything else is passed through as is.
+pub const EVENT_PREFIX: &str = "::dcicd::";
+
+/// what `dcicd-runner` exits with. the first failing step decides it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RunnerExit {
+    Passed,
+    StepFailed,
+    /// a step was killed by a signal.
+    Signal,
+    /// a step ran longer than its `timeout`.
+    Timeout,
+    /// the pipeline file or the runners env is invalid.
+    ConfigError,
+    /// the runner couldn't run a step, e.g. its shell is missing from the image.
+    Error,
+}
+
+impl RunnerExit {
+    pub fn code(self) -> i32 {
+        match self {
+            RunnerExit::Passed => 0,
+            RunnerExit::StepFailed => 1,
+            RunnerExit::Signal => 2,
+            RunnerExit::Timeout => 3,
+            RunnerExit::ConfigError => 4,
+            RunnerExit::Error => 5,
+        }
+    }
+
+    /// `None` for codes the runner doesn't use, e.g. dockers own errors.
+    pub fn from_code(code: i32) -> Option<Self> {
+        Some(match code {
+            0 => RunnerExit::Passed,
+            1 => RunnerExit::StepFailed,
+            2 => RunnerExit::Signal,
+            3 => RunnerExit::Timeout,
+            4 => RunnerExit::ConfigError,
+            5 => RunnerExit::Error,
+            _ => return None,
+        })
+    }
+
+    /// failures are the pipelines fault, errors are cis.
+    pub fn outcome(self) -> Outcome {
+        match self {
+            RunnerExit::Passed => Outcome::Passed,
+            RunnerExit::StepFailed | RunnerExit::Signal | RunnerExit::Timeout => Outcome::Failed,
+            RunnerExit::ConfigError | RunnerExit::Error => Outcome::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// what `dcicd-runner` reports to the backend, one json object per line of stdout.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunnerEvent {
+    StepStarted {
+        step: usize,
+        name: String,
+        /// the image the step runs in, if the runner knows it.
+        image: Option<String>,
+        command: String,
+    },
+    /// a line the step wrote.
+    Output {
+        step: usize,
+        stream: Stream,
+        text: String,
+    },
+    StepFinished {
+        step: usize,
+        name: String,
+        exit_code: Option<i32>,
+        /// set if the step was killed by a signal.
+        signal: Option<i32>,
+        duration_ms: u64,
+        continue_on_error: bool,
+        /// killed for running longer than its `timeout`.
+        #[serde(default)]
+        timed_out: bool,
+        /// what the step wrote to `$DCICD_OUTPUT`.
+        #[serde(default)]
+        outputs: Env,
+        /// the markdown the step wrote to `$DCICD_SUMMARY`.
+        #[serde(default)]
+        markdown: Option<String>,
+    },
+    /// the steps `if` is false.
+    StepSkipped { step: usize, name: String },
+    /// the step failed and is run again, after a `StepFinished` for the failed attempt if it got
+    /// to start.
+    StepRetry {
+        step: usize,
+        name: String,
+        /// the attempt about to start, from 1.
+        attempt: u32,
+        max_attempts: u32,
+        delay_ms: u64,
+        /// why the last attempt failed.
+        reason: String,
+    },
+}
+
+/// how a step went, as parsed from the runners events.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StepResult {
+    pub step: usize,
+    pub name: String,
+    pub image: Option<String>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    /// failed, but the step has `continue_on_error` set.
+    pub allowed_failure: bool,
+    #[serde(default)]
+    pub timed_out: bool,
+    /// why earlier attempts failed, if the step was retried.
+    #[serde(default)]
+    pub retries: Vec<String>,
+    /// what the step wrote to `$DCICD_OUTPUT`.
+    #[serde(default)]
+    pub outputs: Env,
+    /// the markdown the step wrote to `$DCICD_SUMMARY`.
+    #[serde(default)]
+    pub markdown: Option<String>,
+}
+
+/// why a step failed, e.g. `exited with status 1`.
+pub fn failure_cause(
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    timed_out: bool,
+    duration_ms: u64,
+) -> String {
+    match (exit_code, signal) {
+        _ if timed_out => format!("timed out after {}", fmt_duration(duration_ms)),
+        (_, Some(signal)) => format!("killed by signal {signal}"),
+        (Some(code), _) => format!("exited with status {code}"),
+        (None, None) => "was stopped".into(),
+    }
+}
+
+impl RunnerEvent {
+    /// prints the event for the backend.
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(event) => println!("{EVENT_PREFIX}{event}"),
+            Err(e) => eprintln!("failed to serialize event. {e}"),
+        }
+    }
+
+    /// the event, if the line is one.
+    pub fn parse(line: &str) -> Option<Self> {
+        serde_json::from_str(line.strip_prefix(EVENT_PREFIX)?).ok()
+    }
+
+    /// the event as it reads in the logs.
+    pub fn render(&self) -> Option<String> {
+        match self {
+            RunnerEvent::StepStarted {
+                step,
+                name,
+                image: Some(image),
+                command,
+            } => Some(format!("=== step {step}: {name} ({image})\n$> {command}")),
+            RunnerEvent::StepStarted {
+                step,
+                name,
+                image: None,
+                command,
+            } => Some(format!("=== step {step}: {name}\n$> {command}")),
+            RunnerEvent::Output { text, .. } => Some(text.clone()),
+            RunnerEvent::StepSkipped { step, name } => {
+                Some(format!("=== step {step}: {name} (skipped)"))
+            }
+            RunnerEvent::StepRetry {
+                name,
+                attempt,
+                max_attempts,
+                delay_ms,
+                reason,
+                ..
+            } => Some(format!(
+                "step '{name}' {reason}, retrying in {} (attempt {attempt} of {max_attempts}).",
+                fmt_duration(*delay_ms)
+            )),
+            RunnerEvent::StepFinished {
+                name,
+                exit_code: Some(0),
+                outputs,
+                ..
+            } if !outputs.is_empty() => Some(format!(
+                "step '{name}' set outputs {}.",
+                fmt_outputs(outputs)
+            )),
+            RunnerEvent::StepFinished {
+                exit_code: Some(0), ..
+            } => None,
+            RunnerEvent::StepFinished {
+                name,
+                exit_code,
+                continue_on_error,
+                timed_out,
+                duration_ms,
+                ..
+            } => {
+                let mut msg = match exit_code {
+                    _ if *timed_out => format!(
+                        "step '{name}', timed out after {}.",
+                        fmt_duration(*duration_ms)
+                    ),
+                    Some(code) => format!("step '{name}', exited with none-zero status '{code}'."),
+                    None => format!("step '{name}', was cancled by a signal."),
+                };
+
+                if *continue_on_error {
+                    msg.push_str(&format!("\ncontinuing, step '{name}' is allowed to fail."));
+                }
+
+                Some(msg)
+            }
+        }
+    }
+}
+
+impl StepResult {
+    /// a step whose `if` was false.
+    pub fn skipped(step: usize, name: String) -> Self {
+        Self {
+            step,
+            name,
+            image: None,
+            exit_code: None,
+            signal: None,
+            duration_ms: 0,
+            outcome: Outcome::Skipped,
+            allowed_failure: false,
+            timed_out: false,
+            retries: Vec::new(),
+            outputs: Env::new(),
+            markdown: None,
+        }
+    }
+
+    /// ` after N attempts` if the step was retried.
+    fn attempts(&self) -> String {
+        match self.retries.len() {
+            0 => String::new(),
+            retries => format!(" after {} attempts", retries + 1),
+        }
+    }
+
+    /// one line for discord.
+    pub fn summary(&self) -> String {
+        let outcome = if self.allowed_failure {
+            "failed (allowed)".to_string()
+        } else {
+            self.outcome.to_string()
+        };
+
+        format!(
+            "step {} {}: {outcome}{} ({})",
+            self.step,
+            self.name,
+            self.attempts(),
+            fmt_duration(self.duration_ms)
+        )
+    }
+
+    /// why the step failed, for discord.
+    pub fn failure(&self) -> String {
+        let why = failure_cause(
+            self.exit_code,
+            self.signal,
+            self.timed_out,
+            self.duration_ms,
+        );
+
+        format!(
+            "failed at step {} `{}`: {why}{}.",
+            self.step,
+            self.name,
+            self.attempts()
+        )
+    }
+}
+
+/// turns the runners raw output into readable logs and the results of its steps.
+pub fn parse_output(raw: &str) -> (String, Vec<StepResult>) {
+    let mut log = String::new();
+    let mut steps = Vec::new();
+    let mut images = BTreeMap::new();
+    // why the failed attempts of retried steps failed.
+    let mut retries: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+
+    for line in raw.lines() {
+        let Some(event) = RunnerEvent::parse(line) else {
+            log.push_str(line);
+            log.push('\n');
+            continue;
+        };
+
+        if let Some(text) = event.render() {
+            log.push_str(&text);
+            log.push('\n');
+        }
+
+        match event {
+            RunnerEvent::StepStarted { step, image, .. } => {
+                images.insert(step, image);
+            }
+            RunnerEvent::StepFinished {
+                step,
+                name,
+                exit_code,
+                signal,
+                duration_ms,
+                continue_on_error,
+                timed_out,
+                outputs,
+                markdown,
+            } => {
+                let passed = exit_code == Some(0);
+
+                steps.push(StepResult {
+                    step,
+                    name,
+                    image: images.remove(&step).flatten(),
+                    exit_code,
+                    signal,
+                    duration_ms,
+                    outcome: if passed {
+                        Outcome::Passed
+                    } else {
+                        Outcome::Failed
+                    },
+                    allowed_failure: !passed && continue_on_error,
+                    timed_out,
+                    retries: retries.remove(&step).unwrap_or_default(),
+                    outputs,
+                    markdown,
+                });
+            }
+            RunnerEvent::StepSkipped { step, name } => steps.push(StepResult::skipped(step, name)),
+            RunnerEvent::StepRetry { step, reason, .. } => {
+                // only the last attempt is a result.
+                if let Some(i) = steps.iter().rposition(|res| res.step == step) {
+                    let failed = steps.remove(i);
+                    images.insert(step, failed.image);
+                }
+
+                retries.entry(step).or_default().push(reason);
+            }
+            RunnerEvent::Output { .. } => {}
+        }
+    }
+
+    (log, steps)
+}
+
+/// human readable duration.
+pub fn fmt_duration(ms: u64) -> String {
+    match ms {
+        0..60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        _ => format!("{}m{}s", ms / 60_000, ms % 60_000 / 1000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(step: usize, exit_code: i32) -> String {
+        format!(
+            r#"{EVENT_PREFIX}{{"event":"step_finished","step":{step},"name":"build","exit_code":{exit_code},"signal":null,"duration_ms":1500,"continue_on_error":false}}"#
+        )
+    }
+
+    #[test]
+    fn parses_events() {
+        let raw = [
+            format!(
+                r#"{EVENT_PREFIX}{{"event":"step_started","step":1,"name":"build","image":"rust:1","command":"make"}}"#
+            ),
+            format!(
+                r#"{EVENT_PREFIX}{{"event":"output","step":1,"stream":"stdout","text":"compiling"}}"#
+            ),
+            finished(1, 0),
+            format!(r#"{EVENT_PREFIX}{{"event":"step_skipped","step":2,"name":"deploy"}}"#),
+        ]
+        .join("\n");
+        let (log, steps) = parse_output(&raw);
+
+        assert_eq!(
+            log,
+            "=== step 1: build (rust:1)\n$> make\ncompiling\n=== step 2: deploy (skipped)\n"
+        );
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].image.as_deref(), Some("rust:1"));
+        assert_eq!(steps[0].outcome, Outcome::Passed);
+        assert_eq!(steps[1].outcome, Outcome::Skipped);
+    }
+
+    #[test]
+    fn keeps_lines_that_only_look_like_events() {
+        let raw = [
+            // not at the start of the line.
+            format!(r#"echo {EVENT_PREFIX}{{"event":"step_skipped","step":1,"name":"x"}}"#),
+            format!("{EVENT_PREFIX}not json"),
+            EVENT_PREFIX.to_string(),
+            format!(r#"{EVENT_PREFIX}{{"event":"no_such_event","step":1}}"#),
+            format!(r#"{EVENT_PREFIX}{{"event":"step_skipped","step":"one","name":"x"}}"#),
+            "plain output".to_string(),
+        ];
+        let (log, steps) = parse_output(&raw.join("\n"));
+
+        assert_eq!(log, raw.join("\n") + "\n");
+        assert!(steps.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_last_attempt_of_retried_steps() {
+        let raw = [
+            finished(1, 1),
+            format!(
+                r#"{EVENT_PREFIX}{{"event":"step_retry","step":1,"name":"build","attempt":2,"max_attempts":2,"delay_ms":0,"reason":"exited with status 1"}}"#
+            ),
+            finished(1, 0),
+        ]
+        .join("\n");
+        let (_, steps) = parse_output(&raw);
+
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].outcome, Outcome::Passed);
+        assert_eq!(steps[0].retries, ["exited with status 1"]);
+    }
+
+    #[test]
+    fn failed_steps() {
+        let (log, steps) = parse_output(&finished(3, 2));
+
+        assert_eq!(log, "step 'build', exited with none-zero status '2'.\n");
+        assert_eq!(steps[0].outcome, Outcome::Failed);
+        assert_eq!(
+            steps[0].failure(),
+            "failed at step 3 `build`: exited with status 2."
+        );
+    }
+}
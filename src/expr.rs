@@ -0,0 +1,669 @@
+use crate::{ci_cd::Env, inputs::input_var, outputs::output_var};
+use std::fmt;
+
+/// the names `if` expressions can read besides `matrix.NAME`, `inputs.NAME`, `outputs.KEY` and
+/// `env.NAME`, with the built-in variable each one is.
+pub const EXPR_VARS: [(&str, &str); 6] = [
+    ("branch", "DCICD_BRANCH"),
+    ("tag", "DCICD_TAG"),
+    ("event", "DCICD_EVENT"),
+    ("repo", "DCICD_REPO"),
+    ("pipeline", "DCICD_PIPELINE"),
+    ("triggered_by", "DCICD_TRIGGERED_BY"),
+];
+
+/// why an expression couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExprError {
+    /// char offset into the expression.
+    pub at: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (column {})", self.message, self.at + 1)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+/// a parsed `if` expression, e.g. `branch == 'main' && !starts_with(tag, 'v0.')`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    String(String),
+    Bool(bool),
+    /// the name of the variable in the vars, e.g. `DCICD_BRANCH` for `branch`.
+    Var(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    /// nothing before it failed.
+    Success,
+    /// something before it failed.
+    Failure,
+    /// either way.
+    Always,
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+impl Func {
+    const ALL: [(&'static str, Func); 6] = [
+        ("success", Func::Success),
+        ("failure", Func::Failure),
+        ("always", Func::Always),
+        ("starts_with", Func::StartsWith),
+        ("ends_with", Func::EndsWith),
+        ("contains", Func::Contains),
+    ];
+
+    fn arity(self) -> usize {
+        match self {
+            Func::Success | Func::Failure | Func::Always => 0,
+            Func::StartsWith | Func::EndsWith | Func::Contains => 2,
+        }
+    }
+}
+
+/// what an expression evaluates to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Bool(bool),
+}
+
+impl Value {
+    /// empty strings are false.
+    fn truthy(&self) -> bool {
+        match self {
+            Value::String(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+        }
+    }
+
+    fn into_string(self) -> String {
+        match self {
+            Value::String(s) => s,
+            Value::Bool(b) => b.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    Comma,
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+    String(String),
+    Ident(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Comma => write!(f, "`,`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Eq => write!(f, "`==`"),
+            Token::Ne => write!(f, "`!=`"),
+            Token::String(s) => write!(f, "'{s}'"),
+            Token::Ident(ident) => write!(f, "`{ident}`"),
+        }
+    }
+}
+
+fn error<T>(at: usize, message: impl Into<String>) -> Result<T, ExprError> {
+    Err(ExprError {
+        at,
+        message: message.into(),
+    })
+}
+
+/// splits an expression into tokens with the char offset each starts at.
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ExprError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let start = i;
+        let next = chars.get(i + 1).copied();
+
+        let token = match (chars[i], next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => Token::LParen,
+            (')', _) => Token::RParen,
+            (',', _) => Token::Comma,
+            ('&', Some('&')) => Token::And,
+            ('|', Some('|')) => Token::Or,
+            ('=', Some('=')) => Token::Eq,
+            ('!', Some('=')) => Token::Ne,
+            ('!', _) => Token::Not,
+            ('=', _) => return error(start, "unexpected `=`, did you mean `==`?"),
+            (quote @ ('\'' | '"'), _) => {
+                let Some(len) = chars[i + 1..].iter().position(|c| *c == quote) else {
+                    return error(start, "unterminated string");
+                };
+                i += len + 2;
+                tokens.push((
+                    start,
+                    Token::String(chars[start + 1..i - 1].iter().collect()),
+                ));
+                continue;
+            }
+            (c, _) if c.is_alphanumeric() || c == '_' => {
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | '-'))
+                {
+                    i += 1;
+                }
+                tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+                continue;
+            }
+            (c, _) => return error(start, format!("unexpected `{c}`")),
+        };
+
+        i += match token {
+            Token::And | Token::Or | Token::Eq | Token::Ne => 2,
+            _ => 1,
+        };
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+/// a recursive descent parser, loosest binding first.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// where the expression ends, for errors at the end.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn at(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(at, _)| *at)
+            .unwrap_or(self.end)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), ExprError> {
+        if self.eat(token) {
+            return Ok(());
+        }
+
+        match self.peek() {
+            Some(found) => error(self.at(), format!("expected {token}, found {found}")),
+            None => error(self.at(), format!("expected {token}")),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.and()?;
+
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ExprError> {
+        let mut expr = self.not()?;
+
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ExprError> {
+        if self.eat(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        let lhs = self.primary()?;
+
+        if self.eat(&Token::Eq) {
+            Ok(Expr::Eq(Box::new(lhs), Box::new(self.primary()?)))
+        } else if self.eat(&Token::Ne) {
+            Ok(Expr::Ne(Box::new(lhs), Box::new(self.primary()?)))
+        } else {
+            Ok(lhs)
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ExprError> {
+        let at = self.at();
+        let Some(token) = self.peek().cloned() else {
+            return error(at, "expected an expression");
+        };
+        self.pos += 1;
+
+        match token {
+            Token::LParen => {
+                let expr = self.or()?;
+                self.expect(&Token::RParen)?;
+                Ok(expr)
+            }
+            Token::String(s) => Ok(Expr::String(s)),
+            Token::Ident(ident) if ident == "true" => Ok(Expr::Bool(true)),
+            Token::Ident(ident) if ident == "false" => Ok(Expr::Bool(false)),
+            Token::Ident(ident) if self.peek() == Some(&Token::LParen) => self.call(at, &ident),
+            Token::Ident(ident) => var(at, &ident),
+            token => error(at, format!("unexpected {token}")),
+        }
+    }
+
+    fn call(&mut self, at: usize, name: &str) -> Result<Expr, ExprError> {
+        let Some((_, func)) = Func::ALL.iter().find(|(func, _)| *func == name) else {
+            return error(at, format!("unknown function `{name}`"));
+        };
+
+        self.expect(&Token::LParen)?;
+        let mut args = Vec::new();
+
+        if !self.eat(&Token::RParen) {
+            loop {
+                args.push(self.or()?);
+
+                if self.eat(&Token::RParen) {
+                    break;
+                }
+
+                self.expect(&Token::Comma)?;
+            }
+        }
+
+        if args.len() != func.arity() {
+            return error(
+                at,
+                format!(
+                    "`{name}` takes {} arguments, found {}",
+                    func.arity(),
+                    args.len()
+                ),
+            );
+        }
+
+        Ok(Expr::Call(*func, args))
+    }
+}
+
+/// the variable an identifier reads.
+fn var(at: usize, ident: &str) -> Result<Expr, ExprError> {
+    if let Some((_, builtin)) = EXPR_VARS.iter().find(|(name, _)| *name == ident) {
+        return Ok(Expr::Var(builtin.to_string()));
+    }
+
+    match ident.split_once('.') {
+        Some(("matrix", name)) if !name.is_empty() => Ok(Expr::Var(format!("matrix.{name}"))),
+        Some(("inputs", name)) if !name.is_empty() => Ok(Expr::Var(input_var(name))),
+        Some(("outputs", key)) if !key.is_empty() => Ok(Expr::Var(output_var(key))),
+        Some(("env", name)) if !name.is_empty() => Ok(Expr::Var(name.to_string())),
+        _ => error(
+            at,
+            format!(
+                "unknown variable `{ident}`, expected `matrix.NAME`, `inputs.NAME`, `outputs.KEY`, \
+                 `env.NAME` or one of {}",
+                EXPR_VARS
+                    .iter()
+                    .map(|(name, _)| format!("`{name}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+    }
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, ExprError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+            end: src.chars().count(),
+        };
+        let expr = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => error(parser.at(), format!("unexpected {token}")),
+        }
+    }
+
+    fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::String(_) | Expr::Bool(_) | Expr::Var(_) => Vec::new(),
+            Expr::Not(expr) => vec![expr],
+            Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) | Expr::Eq(lhs, rhs) | Expr::Ne(lhs, rhs) => {
+                vec![lhs, rhs]
+            }
+            Expr::Call(_, args) => args.iter().collect(),
+        }
+    }
+
+    /// true if it calls `success()`, `failure()` or `always()`.
+    pub fn checks_status(&self) -> bool {
+        match self {
+            Expr::Call(Func::Success | Func::Failure | Func::Always, _) => true,
+            expr => expr.children().into_iter().any(Expr::checks_status),
+        }
+    }
+
+    /// the `NAME` of every `matrix.NAME` it reads.
+    pub fn matrix_vars(&self) -> Vec<&str> {
+        match self {
+            Expr::Var(name) => name.strip_prefix("matrix.").into_iter().collect(),
+            expr => expr
+                .children()
+                .into_iter()
+                .flat_map(Expr::matrix_vars)
+                .collect(),
+        }
+    }
+
+    fn eval(&self, vars: &Env, failed: bool) -> Value {
+        let string = |expr: &Expr| expr.eval(vars, failed).into_string();
+
+        match self {
+            Expr::String(s) => Value::String(s.clone()),
+            Expr::Bool(b) => Value::Bool(*b),
+            // unset variables are empty.
+            Expr::Var(name) => Value::String(vars.get(name).cloned().unwrap_or_default()),
+            Expr::Not(expr) => Value::Bool(!expr.eval(vars, failed).truthy()),
+            Expr::And(lhs, rhs) => {
+                Value::Bool(lhs.eval(vars, failed).truthy() && rhs.eval(vars, failed).truthy())
+            }
+            Expr::Or(lhs, rhs) => {
+                Value::Bool(lhs.eval(vars, failed).truthy() || rhs.eval(vars, failed).truthy())
+            }
+            Expr::Eq(lhs, rhs) => Value::Bool(string(lhs) == string(rhs)),
+            Expr::Ne(lhs, rhs) => Value::Bool(string(lhs) != string(rhs)),
+            Expr::Call(func, args) => Value::Bool(match (func, args.as_slice()) {
+                (Func::Success, _) => !failed,
+                (Func::Failure, _) => failed,
+                (Func::Always, _) => true,
+                (Func::StartsWith, [s, prefix]) => string(s).starts_with(&string(prefix)),
+                (Func::EndsWith, [s, suffix]) => string(s).ends_with(&string(suffix)),
+                (Func::Contains, [s, part]) => string(s).contains(&string(part)),
+                // the parser checks the arguments.
+                _ => false,
+            }),
+        }
+    }
+
+    /// whether a step or pipeline with this `if` runs. `failed` is whether something before it
+    /// failed. without a status check only `success()` ones run.
+    pub fn holds(&self, vars: &Env, failed: bool) -> bool {
+        let holds = self.eval(vars, failed).truthy();
+
+        if self.checks_status() {
+            holds
+        } else {
+            holds && !failed
+        }
+    }
+}
+
+/// whether a step or pipeline with the `if` `condition` runs. no `if` runs as long as nothing
+/// failed.
+pub fn should_run(condition: Option<&str>, vars: &Env, failed: bool) -> Result<bool, ExprError> {
+    match condition {
+        Some(condition) => Ok(Expr::parse(condition)?.holds(vars, failed)),
+        None => Ok(!failed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn holds(src: &str, vars: &Env, failed: bool) -> bool {
+        Expr::parse(src).unwrap().holds(vars, failed)
+    }
+
+    fn parse_error(src: &str) -> (usize, String) {
+        let e = Expr::parse(src).unwrap_err();
+        (e.at, e.message)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let a = || Box::new(Expr::Bool(true));
+        let b = || Box::new(Expr::Bool(false));
+
+        assert_eq!(
+            Expr::parse("true || false && false").unwrap(),
+            Expr::Or(a(), Box::new(Expr::And(b(), b())))
+        );
+        assert!(holds("true || false && false", &Env::new(), false));
+        assert!(!holds("(true || false) && false", &Env::new(), false));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert_eq!(
+            Expr::parse("!true && false").unwrap(),
+            Expr::And(
+                Box::new(Expr::Not(Box::new(Expr::Bool(true)))),
+                Box::new(Expr::Bool(false))
+            )
+        );
+        assert!(holds("!false && true", &Env::new(), false));
+        assert!(!holds("!(false || true)", &Env::new(), false));
+    }
+
+    #[test]
+    fn comparison_binds_tighter_than_not() {
+        let vars = vars(&[("DCICD_BRANCH", "main")]);
+
+        assert!(!holds("!branch == 'main'", &vars, false));
+        assert!(holds("!branch == 'dev'", &vars, false));
+        assert!(holds("branch == 'dev' || branch == 'main'", &vars, false));
+    }
+
+    #[test]
+    fn compares_strings() {
+        let vars = vars(&[("DCICD_BRANCH", "release/1.2"), ("DCICD_TAG", "")]);
+
+        assert!(holds("branch == 'release/1.2'", &vars, false));
+        assert!(holds("branch != \"main\"", &vars, false));
+        assert!(holds("starts_with(branch, 'release/')", &vars, false));
+        assert!(holds("ends_with(branch, '.2')", &vars, false));
+        assert!(holds("contains(branch, 'ease')", &vars, false));
+        assert!(!holds("contains(branch, 'main')", &vars, false));
+        // empty and unset variables are false.
+        assert!(!holds("tag", &vars, false));
+        assert!(!holds("env.UNSET", &vars, false));
+        assert!(holds("branch", &vars, false));
+        assert!(holds("tag == ''", &vars, false));
+    }
+
+    #[test]
+    fn compares_numbers_as_written() {
+        let vars = vars(&[("matrix.node", "18"), (&input_var("retries"), "3")]);
+
+        assert!(holds("matrix.node == '18'", &vars, false));
+        assert!(holds("matrix.node != '18.0'", &vars, false));
+        assert!(holds("inputs.retries == '3'", &vars, false));
+        assert!(holds("true == 'true'", &vars, false));
+        assert_eq!(
+            parse_error("matrix.node == 18"),
+            (
+                15,
+                "unknown variable `18`, expected `matrix.NAME`, `inputs.NAME`, `outputs.KEY`, \
+                 `env.NAME` or one of `branch`, `tag`, `event`, `repo`, `pipeline`, \
+                 `triggered_by`"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn reads_variables() {
+        assert_eq!(
+            Expr::parse("branch").unwrap(),
+            Expr::Var("DCICD_BRANCH".into())
+        );
+        assert_eq!(
+            Expr::parse("matrix.os").unwrap(),
+            Expr::Var("matrix.os".into())
+        );
+        assert_eq!(
+            Expr::parse("inputs.dry_run").unwrap(),
+            Expr::Var("DCICD_INPUT_DRY_RUN".into())
+        );
+        assert_eq!(
+            Expr::parse("outputs.version").unwrap(),
+            Expr::Var("DCICD_OUTPUT_VERSION".into())
+        );
+        assert_eq!(Expr::parse("env.HOME").unwrap(), Expr::Var("HOME".into()));
+    }
+
+    #[test]
+    fn status_functions() {
+        let vars = Env::new();
+
+        assert!(holds("success()", &vars, false));
+        assert!(!holds("success()", &vars, true));
+        assert!(!holds("failure()", &vars, false));
+        assert!(holds("failure()", &vars, true));
+        assert!(holds("always()", &vars, false));
+        assert!(holds("always()", &vars, true));
+        assert!(holds("always() && !failure()", &vars, false));
+    }
+
+    #[test]
+    fn only_status_checks_run_after_a_failure() {
+        let vars = vars(&[("DCICD_BRANCH", "main")]);
+
+        assert!(holds("branch == 'main'", &vars, false));
+        assert!(!holds("branch == 'main'", &vars, true));
+        assert!(holds("failure() && branch == 'main'", &vars, true));
+        assert_eq!(should_run(None, &vars, false), Ok(true));
+        assert_eq!(should_run(None, &vars, true), Ok(false));
+        assert_eq!(should_run(Some("always()"), &vars, true), Ok(true));
+        assert!(should_run(Some("branch =="), &vars, false).is_err());
+    }
+
+    #[test]
+    fn checks_status() {
+        assert!(Expr::parse("failure()").unwrap().checks_status());
+        assert!(Expr::parse("branch == 'main' || always()")
+            .unwrap()
+            .checks_status());
+        assert!(Expr::parse("!(success())").unwrap().checks_status());
+        assert!(!Expr::parse("branch == 'main'").unwrap().checks_status());
+        assert!(!Expr::parse("contains(branch, 'failure()')")
+            .unwrap()
+            .checks_status());
+    }
+
+    #[test]
+    fn matrix_vars() {
+        let expr = Expr::parse(
+            "matrix.os == 'linux' && (starts_with(matrix.node, '1') || !matrix.nightly) \
+             && branch == 'matrix.fake'",
+        )
+        .unwrap();
+
+        assert_eq!(expr.matrix_vars(), vec!["os", "node", "nightly"]);
+        assert!(Expr::parse("always()").unwrap().matrix_vars().is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_error("branch = 'main'"),
+            (7, "unexpected `=`, did you mean `==`?".into())
+        );
+        assert_eq!(
+            parse_error("branch == 'main"),
+            (10, "unterminated string".into())
+        );
+        assert_eq!(
+            parse_error("branch == "),
+            (10, "expected an expression".into())
+        );
+        assert_eq!(
+            parse_error("(branch == 'main'"),
+            (17, "expected `)`".into())
+        );
+        assert_eq!(
+            parse_error("branch 'main'"),
+            (7, "unexpected 'main'".into())
+        );
+        assert_eq!(parse_error("branch # x"), (7, "unexpected `#`".into()));
+        assert_eq!(parse_error("nope()"), (0, "unknown function `nope`".into()));
+        assert_eq!(
+            parse_error("starts_with(branch)"),
+            (0, "`starts_with` takes 2 arguments, found 1".into())
+        );
+        assert_eq!(
+            parse_error("failure(branch)"),
+            (0, "`failure` takes 0 arguments, found 1".into())
+        );
+        assert_eq!(
+            parse_error("contains(branch 'x')"),
+            (16, "expected `,`, found 'x'".into())
+        );
+        assert!(parse_error("matrix.")
+            .1
+            .starts_with("unknown variable `matrix.`"));
+        assert!(parse_error("foo").1.starts_with("unknown variable `foo`"));
+    }
+
+    #[test]
+    fn error_columns_count_chars() {
+        let e = Expr::parse("'é' == é =").unwrap_err();
+
+        assert_eq!(e.at, 9);
+        assert_eq!(
+            e.to_string(),
+            "unexpected `=`, did you mean `==`? (column 10)"
+        );
+    }
+}
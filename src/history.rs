@@ -0,0 +1,163 @@
+use crate::{
+    approval::ApprovalRecord,
+    ci_cd::{Env, Outcome, PipelineName, RepoName, RunEvent, RunId, STATE_DIR},
+    events::StepResult,
+    secrets::mask,
+};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{create_dir_all, read_dir, read_to_string, write},
+    path::PathBuf,
+};
+
+pub const RUNS_DIR: &str = "runs";
+
+/// what happened in a pipeline run, kept in `STATE_DIR/runs/<run_id>.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RunRecord {
+    pub run_id: RunId,
+    pub repo: RepoName,
+    pub commit_sha: String,
+    pub branch: String,
+    pub pipeline: PipelineName,
+    pub triggered_by: String,
+    /// not known for runs from before it was recorded.
+    #[serde(default)]
+    pub event: Option<RunEvent>,
+    /// the run this one ran again.
+    #[serde(default)]
+    pub rerun_of: Option<RunId>,
+    /// input name -> value, what re-runs are given again.
+    #[serde(default)]
+    pub inputs: Env,
+    /// unix time.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    pub jobs: Vec<JobRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct JobRecord {
+    pub name: PipelineName,
+    pub outcome: Outcome,
+    /// why the job failed before running any steps, or how an approval was decided.
+    pub reason: Option<String>,
+    /// one per matrix combination, or a single one without a matrix.
+    pub cells: Vec<CellRecord>,
+    /// who approved or rejected an approval job.
+    #[serde(default)]
+    pub approval: Option<ApprovalRecord>,
+    /// what its steps wrote to `$DCICD_OUTPUT`, later steps winning.
+    #[serde(default)]
+    pub outputs: Env,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CellRecord {
+    pub matrix: Env,
+    pub outcome: Outcome,
+    /// why it failed, e.g. which step.
+    pub reason: Option<String>,
+    pub steps: Vec<StepResult>,
+    /// why earlier attempts failed, if the pipeline was retried.
+    #[serde(default)]
+    pub retries: Vec<String>,
+}
+
+impl JobRecord {
+    /// a job that never got to run its steps.
+    pub fn not_run(name: &str, outcome: Outcome, reason: Option<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            outcome,
+            reason,
+            cells: Vec::new(),
+            approval: None,
+            outputs: Env::new(),
+        }
+    }
+
+    /// the record with every secret value in what its steps named, wrote or failed with masked,
+    /// for keeping it on disk.
+    pub fn masked(self, secrets: &Env) -> Self {
+        let mask_all = |text: Vec<String>| text.iter().map(|text| mask(text, secrets)).collect();
+        let mask_env = |env: Env| {
+            env.into_iter()
+                .map(|(name, value)| (name, mask(&value, secrets)))
+                .collect()
+        };
+
+        Self {
+            reason: self.reason.map(|reason| mask(&reason, secrets)),
+            cells: self
+                .cells
+                .into_iter()
+                .map(|cell| CellRecord {
+                    reason: cell.reason.map(|reason| mask(&reason, secrets)),
+                    steps: cell
+                        .steps
+                        .into_iter()
+                        .map(|step| StepResult {
+                            name: mask(&step.name, secrets),
+                            retries: mask_all(step.retries),
+                            outputs: mask_env(step.outputs),
+                            markdown: step.markdown.map(|markdown| mask(&markdown, secrets)),
+                            ..step
+                        })
+                        .collect(),
+                    retries: mask_all(cell.retries),
+                    ..cell
+                })
+                .collect(),
+            outputs: mask_env(self.outputs),
+            ..self
+        }
+    }
+}
+
+fn runs_dir() -> PathBuf {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(RUNS_DIR);
+
+    path
+}
+
+pub fn save(record: &RunRecord) -> Result<()> {
+    create_dir_all(runs_dir())?;
+    write(
+        runs_dir().join(format!("{}.json", record.run_id)),
+        serde_json::to_string_pretty(record)?,
+    )?;
+
+    Ok(())
+}
+
+pub fn load(run_id: RunId) -> Result<RunRecord> {
+    let Ok(record) = read_to_string(runs_dir().join(format!("{run_id}.json"))) else {
+        bail!("no record of run {run_id}");
+    };
+
+    Ok(serde_json::from_str(&record)?)
+}
+
+/// the latest `count` runs, newest first. only of `repo` if one is given.
+pub fn recent(repo: Option<&str>, count: usize) -> Vec<RunRecord> {
+    let mut run_ids: Vec<RunId> = read_dir(runs_dir())
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.path().file_stem()?.to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    run_ids.sort_by_key(|run_id| std::cmp::Reverse(*run_id));
+
+    run_ids
+        .into_iter()
+        .filter_map(|run_id| load(run_id).ok())
+        .filter(|record| repo.is_none_or(|repo| record.repo == repo))
+        .take(count)
+        .collect()
+}
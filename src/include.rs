@@ -0,0 +1,179 @@
+use crate::ci_cd::{RepoName, STATE_DIR};
+use anyhow::{bail, Result};
+use git2::{Commit, Repository, Tree};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+pub const INCLUDE_MIRROR_DIR: &str = "includes";
+
+/// a file whose templates and pipelines are added to the including one. pipelines and templates
+/// of the including file win over included ones of the same name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Include {
+    /// relative to the root of the repo it's in.
+    pub file: PathBuf,
+    /// a registered repo to include the file from. defaults to the including files repo.
+    pub repo: Option<RepoName>,
+    /// the tag, branch or commit of `repo` to include the file at. required with `repo`.
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+}
+
+/// the repo an included file is read from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    /// the repo being run.
+    Local,
+    Repo {
+        repo: RepoName,
+        git_ref: String,
+    },
+}
+
+impl Origin {
+    /// where `include` is read from, when included from a file of this origin.
+    pub fn of(&self, include: &Include) -> Result<Origin> {
+        match (&include.repo, &include.git_ref) {
+            (Some(repo), Some(git_ref)) => Ok(Origin::Repo {
+                repo: repo.clone(),
+                git_ref: git_ref.clone(),
+            }),
+            (Some(repo), None) => bail!("including from {repo} needs a pinned `ref`"),
+            (None, Some(_)) => bail!("`ref` needs a `repo`"),
+            (None, None) => Ok(self.clone()),
+        }
+    }
+
+    /// how the file is named in diagnostics.
+    pub fn name(&self, file: &Path) -> String {
+        match self {
+            Origin::Local => file.display().to_string(),
+            Origin::Repo { repo, git_ref } => format!("{repo}@{git_ref}:{}", file.display()),
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Local => write!(f, "this repo"),
+            Origin::Repo { repo, git_ref } => write!(f, "{repo}@{git_ref}"),
+        }
+    }
+}
+
+/// reads a file of the repo being run.
+type ReadLocal<'a> = Box<dyn Fn(&Path) -> Result<String> + 'a>;
+
+/// reads the files pipeline files include.
+pub struct Includes<'a> {
+    local: ReadLocal<'a>,
+    /// the registered repos files can be included from.
+    repos: HashMap<RepoName, Url>,
+}
+
+impl<'a> Includes<'a> {
+    pub fn new(
+        local: impl Fn(&Path) -> Result<String> + 'a,
+        repos: HashMap<RepoName, Url>,
+    ) -> Self {
+        Self {
+            local: Box::new(local),
+            repos,
+        }
+    }
+
+    /// local includes are read from `dir`.
+    pub fn from_dir(dir: &'a Path, repos: HashMap<RepoName, Url>) -> Self {
+        Self::new(move |file| Ok(read_to_string(dir.join(file))?), repos)
+    }
+
+    /// local includes are read from a commit.
+    pub fn from_tree(
+        mirror: &'a Repository,
+        tree: Tree<'a>,
+        repos: HashMap<RepoName, Url>,
+    ) -> Self {
+        Self::new(move |file| read_blob(mirror, &tree, file), repos)
+    }
+
+    pub fn read(&self, origin: &Origin, file: &Path) -> Result<String> {
+        match origin {
+            Origin::Local => (self.local)(file),
+            Origin::Repo { repo, git_ref } => {
+                let Some(url) = self.repos.get(repo) else {
+                    bail!("{repo} is not a registered repo");
+                };
+
+                fetch(repo, url, git_ref, file)
+            }
+        }
+    }
+}
+
+/// the commit a tag, branch or sha points to in a mirror.
+fn find<'r>(mirror: &'r Repository, git_ref: &str) -> Option<Commit<'r>> {
+    [
+        format!("refs/tags/{git_ref}"),
+        format!("refs/remotes/origin/{git_ref}"),
+        git_ref.to_string(),
+    ]
+    .iter()
+    .find_map(|name| mirror.revparse_single(name).ok()?.peel_to_commit().ok())
+}
+
+fn read_blob(mirror: &Repository, tree: &Tree, file: &Path) -> Result<String> {
+    let Ok(entry) = tree.get_path(file) else {
+        bail!("no such file {}", file.display());
+    };
+    let blob = entry.to_object(mirror)?.peel_to_blob()?;
+
+    Ok(String::from_utf8_lossy(blob.content()).to_string())
+}
+
+/// reads `file` from `repo` at `git_ref`, fetching the repo into a bare mirror under `STATE_DIR`.
+/// commits already in the mirror aren't fetched again.
+pub fn fetch(repo: &str, url: &Url, git_ref: &str, file: &Path) -> Result<String> {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(INCLUDE_MIRROR_DIR);
+    path.push(format!("{repo}.git"));
+
+    let mirror = match Repository::open_bare(&path) {
+        Ok(mirror) => mirror,
+        Err(_) => Repository::init_bare(&path)?,
+    };
+
+    // a commit never changes, tags and branches might have moved.
+    let is_commit = git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit());
+    let commit = match find(&mirror, git_ref) {
+        Some(commit) if is_commit => commit,
+        _ => {
+            mirror.remote_anonymous(url.as_str())?.fetch(
+                &[
+                    "+refs/heads/*:refs/remotes/origin/*",
+                    "+refs/tags/*:refs/tags/*",
+                ],
+                None,
+                None,
+            )?;
+
+            match find(&mirror, git_ref) {
+                Some(commit) => commit,
+                None => bail!("{repo} has no ref {git_ref}"),
+            }
+        }
+    };
+
+    let tree = commit.tree()?;
+    let source = read_blob(&mirror, &tree, file)?;
+
+    Ok(source)
+}
@@ -0,0 +1,352 @@
+use crate::ci_cd::Env;
+use anyhow::{anyhow, bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+
+/// inputs are given to steps as `DCICD_INPUT_<NAME>`.
+pub const INPUT_VAR_PREFIX: &str = "DCICD_INPUT_";
+
+/// input name -> what it takes.
+pub type Inputs = BTreeMap<String, Input>;
+
+/// the variable an input is given to steps as.
+pub fn input_var(name: &str) -> String {
+    format!("{INPUT_VAR_PREFIX}{}", name.to_uppercase())
+}
+
+/// a value given when the pipeline is run from discord. available to steps as
+/// `DCICD_INPUT_<NAME>` and to `if`s as `inputs.NAME`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Input {
+    /// defaults to `string`.
+    #[serde(rename = "type", default)]
+    pub kind: InputKind,
+    /// shown when asking for the input.
+    pub description: Option<String>,
+    /// used if the input isn't given. inputs without one are required.
+    pub default: Option<InputValue>,
+    /// the values a `choice` input can have.
+    pub options: Option<Vec<String>>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+    /// any text.
+    #[default]
+    String,
+    /// `true` or `false`.
+    Bool,
+    /// one of `options`.
+    Choice,
+    /// an integer or decimal.
+    Number,
+}
+
+/// a default, written as the type of its input.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum InputValue {
+    Bool(bool),
+    Number(serde_json::Number),
+    String(String),
+}
+
+impl fmt::Display for InputValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputValue::Bool(val) => write!(f, "{val}"),
+            InputValue::Number(val) => write!(f, "{val}"),
+            InputValue::String(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+// numbers can't be ordered by derive, so values are ordered as they're written.
+impl PartialOrd for InputValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InputValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_string().cmp(&other.to_string())
+    }
+}
+
+impl Input {
+    /// what the input takes, e.g. as a placeholder when asking for it.
+    pub fn hint(&self) -> String {
+        match self.kind {
+            InputKind::String => "text".into(),
+            InputKind::Bool => "true or false".into(),
+            InputKind::Choice => format!(
+                "one of {}",
+                self.options
+                    .iter()
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            InputKind::Number => "a number".into(),
+        }
+    }
+
+    /// checks a value of the input, returning it the way steps get it.
+    pub fn parse(&self, value: &str) -> Result<String> {
+        let trimmed = value.trim();
+
+        match self.kind {
+            InputKind::String => Ok(value.to_string()),
+            InputKind::Bool => match trimmed.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Ok("true".into()),
+                "false" | "no" | "off" | "0" => Ok("false".into()),
+                _ => bail!("expected true or false, found `{trimmed}`"),
+            },
+            InputKind::Choice => {
+                let options = self.options.as_deref().unwrap_or_default();
+
+                if options.iter().any(|option| option == trimmed) {
+                    Ok(trimmed.to_string())
+                } else {
+                    bail!("expected {}, found `{trimmed}`", self.hint())
+                }
+            }
+            InputKind::Number => match trimmed.parse::<f64>() {
+                Ok(number) if number.is_finite() => Ok(trimmed.to_string()),
+                _ => bail!("expected a number, found `{trimmed}`"),
+            },
+        }
+    }
+}
+
+/// checks the inputs a run was given against the ones its pipeline takes, filling in defaults.
+pub fn resolve(inputs: Option<&Inputs>, given: &Env) -> Result<Env> {
+    let none = Inputs::new();
+    let inputs = inputs.unwrap_or(&none);
+
+    if let Some(name) = given.keys().find(|name| !inputs.contains_key(*name)) {
+        bail!("the pipeline has no input `{name}`");
+    }
+
+    inputs
+        .iter()
+        .map(|(name, input)| {
+            let value = match (given.get(name), &input.default) {
+                (Some(value), _) => value.clone(),
+                (None, Some(default)) => default.to_string(),
+                (None, None) => bail!("missing input `{name}`"),
+            };
+            let value = input
+                .parse(&value)
+                .map_err(|e| anyhow!("invalid input `{name}`, {e}"))?;
+
+            Ok((name.clone(), value))
+        })
+        .collect()
+}
+
+/// reads `name=value` pairs separated by commas. values can have commas in them too, only a part
+/// with a `=` starts a new input.
+pub fn parse_pairs(s: &str) -> Result<Env> {
+    let mut pairs = Env::new();
+    let mut last = None;
+
+    for part in s.split(',') {
+        match (part.split_once('='), &last) {
+            (Some((name, value)), _) => {
+                let name = name.trim().to_string();
+                pairs.insert(name.clone(), value.trim().to_string());
+                last = Some(name);
+            }
+            (None, Some(name)) => {
+                if let Some(value) = pairs.get_mut(name) {
+                    value.push(',');
+                    value.push_str(part.trim_end());
+                }
+            }
+            (None, None) if part.trim().is_empty() => {}
+            (None, None) => bail!("expected `name=value`, found `{}`", part.trim()),
+        }
+    }
+
+    Ok(pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(kind: InputKind, default: Option<InputValue>) -> Input {
+        Input {
+            kind,
+            description: None,
+            default,
+            options: (kind == InputKind::Choice).then(|| vec!["staging".into(), "prod".into()]),
+        }
+    }
+
+    fn env(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn resolve_error(inputs: &Inputs, given: &[(&str, &str)]) -> String {
+        resolve(Some(inputs), &env(given)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_bools() {
+        let input = input(InputKind::Bool, None);
+
+        for value in ["true", "Yes", " on ", "1"] {
+            assert_eq!(input.parse(value).unwrap(), "true");
+        }
+
+        for value in ["false", "NO", "off", "0"] {
+            assert_eq!(input.parse(value).unwrap(), "false");
+        }
+
+        assert_eq!(
+            input.parse("maybe").unwrap_err().to_string(),
+            "expected true or false, found `maybe`"
+        );
+    }
+
+    #[test]
+    fn parses_numbers() {
+        let input = input(InputKind::Number, None);
+
+        assert_eq!(input.parse(" 42 ").unwrap(), "42");
+        assert_eq!(input.parse("-1.50").unwrap(), "-1.50");
+        assert!(input.parse("1e3").is_ok());
+        assert!(input.parse("inf").is_err());
+        assert!(input.parse("NaN").is_err());
+        assert_eq!(
+            input.parse("ten").unwrap_err().to_string(),
+            "expected a number, found `ten`"
+        );
+    }
+
+    #[test]
+    fn checks_choices() {
+        let input = input(InputKind::Choice, None);
+
+        assert_eq!(input.parse(" prod ").unwrap(), "prod");
+        assert_eq!(
+            input.parse("Prod").unwrap_err().to_string(),
+            "expected one of staging, prod, found `Prod`"
+        );
+        // a choice without options takes nothing.
+        assert!(Input {
+            options: None,
+            ..input
+        }
+        .parse("prod")
+        .is_err());
+    }
+
+    #[test]
+    fn keeps_strings_as_given() {
+        assert_eq!(
+            input(InputKind::String, None).parse(" two words ").unwrap(),
+            " two words "
+        );
+    }
+
+    #[test]
+    fn resolves_with_defaults() {
+        let inputs = Inputs::from([
+            ("env".into(), input(InputKind::Choice, None)),
+            (
+                "dry_run".into(),
+                input(InputKind::Bool, Some(InputValue::Bool(false))),
+            ),
+            (
+                "replicas".into(),
+                input(InputKind::Number, Some(InputValue::Number(3.into()))),
+            ),
+        ]);
+
+        assert_eq!(
+            resolve(
+                Some(&inputs),
+                &env(&[("env", "staging"), ("dry_run", "yes")])
+            )
+            .unwrap(),
+            env(&[("env", "staging"), ("dry_run", "true"), ("replicas", "3")])
+        );
+        assert_eq!(resolve(None, &Env::new()).unwrap(), Env::new());
+    }
+
+    #[test]
+    fn rejects_missing_unknown_and_invalid_inputs() {
+        let inputs = Inputs::from([
+            ("env".into(), input(InputKind::Choice, None)),
+            ("replicas".into(), input(InputKind::Number, None)),
+        ]);
+
+        assert_eq!(
+            resolve_error(&inputs, &[("env", "prod")]),
+            "missing input `replicas`"
+        );
+        assert_eq!(
+            resolve_error(
+                &inputs,
+                &[("env", "prod"), ("replicas", "2"), ("force", "1")]
+            ),
+            "the pipeline has no input `force`"
+        );
+        assert_eq!(
+            resolve_error(&inputs, &[("env", "dev"), ("replicas", "2")]),
+            "invalid input `env`, expected one of staging, prod, found `dev`"
+        );
+        assert_eq!(
+            resolve(None, &env(&[("env", "prod")]))
+                .unwrap_err()
+                .to_string(),
+            "the pipeline has no input `env`"
+        );
+    }
+
+    #[test]
+    fn checks_defaults_too() {
+        let inputs = Inputs::from([(
+            "replicas".into(),
+            input(InputKind::Number, Some(InputValue::String("many".into()))),
+        )]);
+
+        assert_eq!(
+            resolve_error(&inputs, &[]),
+            "invalid input `replicas`, expected a number, found `many`"
+        );
+    }
+
+    #[test]
+    fn parses_pairs() {
+        assert_eq!(
+            parse_pairs("env=prod, replicas = 3").unwrap(),
+            env(&[("env", "prod"), ("replicas", "3")])
+        );
+        assert_eq!(parse_pairs("").unwrap(), Env::new());
+        // only parts with a `=` start a new input.
+        assert_eq!(
+            parse_pairs("tags=a,b ,c,url=https://x.y/?q=1").unwrap(),
+            env(&[("tags", "a,b,c"), ("url", "https://x.y/?q=1")])
+        );
+        // later pairs win.
+        assert_eq!(parse_pairs("a=1,a=2").unwrap(), env(&[("a", "2")]));
+        assert_eq!(
+            parse_pairs("prod,env=prod").unwrap_err().to_string(),
+            "expected `name=value`, found `prod`"
+        );
+    }
+}
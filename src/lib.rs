@@ -1,14 +1,65 @@
-#![feature(async_closure)]
-use ci_cd::{Backend, BackendState, CiCdCmd, PipelineName, Repo, RepoName};
+use approval::{Approval, ApprovalRequest, Decision};
+use ci_cd::{
+    Backend, BackendState, ChannelId, CiCdCmd, Env, OnComplete, Outcome, PipelineName, QueuedRun,
+    Repo, RepoName, Rerun, RunEvent, RunId, RunReport, CACHE_DIR,
+};
 use crossbeam::channel::{unbounded, Receiver, Sender};
-use poise::serenity_prelude::futures::lock::{Mutex, MutexGuard};
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use environments::{Deployment, Environment, Environments, Protection};
+use include::Includes;
+use inputs::Inputs;
+use poise::serenity_prelude::{
+    self as serenity,
+    futures::lock::{Mutex, MutexGuard},
+    Attachment, ButtonStyle, ComponentInteraction, CreateActionRow, CreateAttachment, CreateButton,
+    CreateEmbed, CreateEmbedFooter, CreateInputText, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateModal, EditMessage, InputTextStyle,
+};
+use poll::PollState;
+use secrets::{GuildId, SecretScope, SecretStore};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use summary::{MAX_EMBEDS, MAX_EMBED_LEN};
+use tokio::{task::spawn_blocking, time::sleep};
 use url::Url;
 
+pub mod approval;
+pub mod artifacts;
+pub mod cache;
 pub mod ci_cd;
+pub mod document;
+pub mod environments;
+pub mod events;
+pub mod expr;
+pub mod history;
+pub mod include;
+pub mod inputs;
+pub mod lint;
+pub mod outputs;
+pub mod poll;
+pub mod secrets;
+pub mod services;
+pub mod summary;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// env var holding the public url of `dcicd-server`, used for artifact download links.
+pub const ARTIFACT_URL_VAR: &str = "DCICD_ARTIFACT_URL";
+/// custom ids of the buttons on run reports, as `<button>:<run_id>`.
+const RERUN_BUTTON: &str = "dcicd-rerun";
+const RERUN_FAILED_BUTTON: &str = "dcicd-rerun-failed";
+/// custom ids of the buttons on approval requests, as `<button>:<ApprovalRequest::key>`.
+const APPROVE_BUTTON: &str = "dcicd-approve";
+const REJECT_BUTTON: &str = "dcicd-reject";
+const NOT_PENDING: &str = "this approval is no longer pending.";
+/// the most fields discord allows in a modal.
+const MAX_MODAL_FIELDS: usize = 5;
 type Context<'a> = poise::Context<'a, Arc<Mutex<Data>>, Error>;
+type ApplicationContext<'a> = poise::ApplicationContext<'a, Arc<Mutex<Data>>, Error>;
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, poise::ChoiceParameter, Debug)]
 pub enum ShowArgs {
@@ -37,12 +88,190 @@ impl FromStr for ShowArgs {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, poise::ChoiceParameter, Debug)]
+pub enum SecretScopeArgs {
+    Repo,
+    Guild,
+}
+
+#[derive(Debug, poise::Modal)]
+#[name = "Set Secret"]
+struct SecretModal {
+    #[name = "Name"]
+    #[placeholder = "REGISTRY_PASSWORD"]
+    name: String,
+    #[name = "Value"]
+    #[paragraph]
+    value: String,
+}
+
+/// asks for the inputs of a pipeline. its fields depend on the pipeline, so they're passed in as
+/// the defaults. required inputs come first, optional ones past `MAX_MODAL_FIELDS` get their
+/// defaults.
+#[derive(Debug, Default)]
+struct InputsModal {
+    pipeline: PipelineName,
+    inputs: Inputs,
+    /// input name -> what was entered. empty fields are left out.
+    values: Env,
+}
+
+impl poise::Modal for InputsModal {
+    fn create(defaults: Option<Self>, custom_id: String) -> CreateInteractionResponse {
+        let InputsModal {
+            pipeline, inputs, ..
+        } = defaults.unwrap_or_default();
+        // discord limits titles and labels to 45 chars, placeholders to 100.
+        let truncate = |s: &str, len: usize| s.chars().take(len).collect::<String>();
+        let mut inputs: Vec<_> = inputs.iter().collect();
+        inputs.sort_by_key(|(_, input)| input.default.is_some());
+
+        let fields = inputs
+            .into_iter()
+            .take(MAX_MODAL_FIELDS)
+            .map(|(name, input)| {
+                let label = truncate(name, 45);
+                let placeholder = input.description.clone().unwrap_or(input.hint());
+                let mut field = CreateInputText::new(InputTextStyle::Short, label, name)
+                    .placeholder(truncate(&placeholder, 100))
+                    .required(input.default.is_none());
+
+                if let Some(default) = &input.default {
+                    field = field.value(default.to_string());
+                }
+
+                CreateActionRow::InputText(field)
+            })
+            .collect();
+
+        CreateInteractionResponse::Modal(
+            CreateModal::new(custom_id, truncate(&format!("run {pipeline}"), 45))
+                .components(fields),
+        )
+    }
+
+    fn parse(data: serenity::ModalInteractionData) -> Result<Self, &'static str> {
+        let values = data
+            .components
+            .iter()
+            .flat_map(|row| row.components.iter())
+            .filter_map(|component| match component {
+                serenity::ActionRowComponent::InputText(text) => Some((
+                    text.custom_id.clone(),
+                    text.value.clone().filter(|value| !value.is_empty())?,
+                )),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Self {
+            values,
+            ..Default::default()
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Data {
     pub git_links: HashMap<RepoName, Url>,
     pub backend: Arc<Mutex<Backend>>,
     pub send_cmd: Sender<CiCdCmd>,
     pub get_output: Receiver<String>,
+    pub poller: Arc<Mutex<PollState>>,
+    pub approvals: Approvals,
+}
+
+/// the re-run buttons of a run report. none if the report isn't about a run.
+fn rerun_buttons(report: &RunReport) -> Vec<CreateActionRow> {
+    let Some(run_id) = report.run_id else {
+        return Vec::new();
+    };
+
+    let mut buttons = vec![CreateButton::new(format!("{RERUN_BUTTON}:{run_id}"))
+        .label("Re-run")
+        .style(ButtonStyle::Secondary)];
+
+    if matches!(report.outcome, Some(Outcome::Failed | Outcome::Error)) {
+        buttons.push(
+            CreateButton::new(format!("{RERUN_FAILED_BUTTON}:{run_id}"))
+                .label("Re-run failed")
+                .style(ButtonStyle::Primary),
+        );
+    }
+
+    vec![CreateActionRow::Buttons(buttons)]
+}
+
+/// the files of a run report that could be read.
+async fn report_attachments(report: &RunReport) -> Vec<CreateAttachment> {
+    let mut attachments = Vec::new();
+
+    for path in report.attachments.iter() {
+        match CreateAttachment::path(path).await {
+            Ok(attachment) => attachments.push(attachment),
+            Err(e) => eprintln!("failed to attach {path:?}: {e}"),
+        }
+    }
+
+    attachments
+}
+
+/// the summary of a run report as embeds, cut off if there's too much of it.
+fn summary_embeds(report: &RunReport) -> Vec<CreateEmbed> {
+    let Some(summary) = &report.summary else {
+        return Vec::new();
+    };
+
+    let mut parts = summary::split(summary, MAX_EMBED_LEN);
+    let cut_off = parts.len() > MAX_EMBEDS;
+    parts.truncate(MAX_EMBEDS);
+    let last = parts.len().saturating_sub(1);
+
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let mut embed = CreateEmbed::new().description(part);
+
+            if i == 0 {
+                embed = embed.title("Summary");
+            }
+
+            if i == last && cut_off {
+                embed = embed.footer(CreateEmbedFooter::new(
+                    "the rest of the summary was cut off.",
+                ));
+            }
+
+            embed
+        })
+        .collect()
+}
+
+/// replies with a run report, attaching its files.
+async fn send_report(ctx: Context<'_>, report: RunReport) -> Result<(), Error> {
+    let mut embeds = summary_embeds(&report).into_iter();
+    let mut reply = poise::CreateReply::default()
+        .components(rerun_buttons(&report))
+        .reply(true);
+
+    if let Some(embed) = embeds.next() {
+        reply = reply.embed(embed);
+    }
+
+    for attachment in report_attachments(&report).await {
+        reply = reply.attachment(attachment);
+    }
+
+    ctx.send(reply.content(report.msg)).await?;
+
+    // discord limits the text of all the embeds of a message, so the rest of a long summary
+    // follows in messages of its own.
+    for embed in embeds {
+        ctx.send(poise::CreateReply::default().embed(embed)).await?;
+    }
+
+    Ok(())
 }
 
 /// registers a git repo to be able to CICD it.
@@ -56,7 +285,14 @@ pub async fn resgister(
         Context::Prefix(data) => {
             let response = if git_url.to_string().ends_with(".git") {
                 let repo_name = git_url.path().replacen("/", "", 1).replace(".git", "");
-                data.data.lock().await.git_links.insert(repo_name, git_url);
+                let mut data = data.data.lock().await;
+                // pipelines include files from registered repos.
+                data.backend
+                    .lock()
+                    .await
+                    .repos
+                    .insert(repo_name.clone(), git_url.clone());
+                data.git_links.insert(repo_name, git_url);
                 "added. now tracking the requested repo."
             } else {
                 "that is not a valiud git link"
@@ -67,7 +303,14 @@ pub async fn resgister(
         Context::Application(data) => {
             let response = if git_url.to_string().ends_with(".git") {
                 let repo_name = git_url.path().replacen("/", "", 1).replace(".git", "");
-                data.data.lock().await.git_links.insert(repo_name, git_url);
+                let mut data = data.data.lock().await;
+                // pipelines include files from registered repos.
+                data.backend
+                    .lock()
+                    .await
+                    .repos
+                    .insert(repo_name.clone(), git_url.clone());
+                data.git_links.insert(repo_name, git_url);
                 "added. now tracking the requested repo."
             } else {
                 "that is not a valiud git link"
@@ -181,51 +424,595 @@ pub async fn load(
     Ok(())
 }
 
+/// asks for the inputs of a pipeline of the loaded repo in a modal. none if the modal wasn't
+/// submitted, or the pipeline has more required inputs than fit in one.
+async fn ask_for_inputs(ctx: ApplicationContext<'_>, pipeline: &str) -> Result<Option<Env>, Error> {
+    let repos = ctx.data.lock().await.git_links.clone();
+    // a broken pipeline file is reported by the backend.
+    let inputs = ci_cd::read_pipeline_file(Path::new(CACHE_DIR))
+        .ok()
+        .and_then(|(file, source)| {
+            let includes = Includes::from_dir(Path::new(CACHE_DIR), repos);
+            let (pipelines, _) = lint::load(file, &source, &includes).ok()?;
+
+            pipelines.get(pipeline)?.inputs.clone()
+        })
+        .unwrap_or_default();
+
+    if inputs.is_empty() {
+        return Ok(Some(Env::new()));
+    }
+
+    let required = inputs
+        .values()
+        .filter(|input| input.default.is_none())
+        .count();
+
+    if required > MAX_MODAL_FIELDS {
+        ctx.reply(format!(
+            "{pipeline} has {required} required inputs, a modal can only ask for {MAX_MODAL_FIELDS}. pass them with the `inputs` option, e.g. `name=value,other=value`."
+        ))
+        .await?;
+
+        return Ok(None);
+    }
+
+    let modal = InputsModal {
+        pipeline: pipeline.to_string(),
+        inputs,
+        values: Env::new(),
+    };
+
+    Ok(poise::execute_modal(ctx, Some(modal), None)
+        .await?
+        .map(|modal| modal.values))
+}
+
 #[poise::command(slash_command, prefix_command)]
 pub async fn run(
     ctx: Context<'_>,
     #[description = "which pipeline to run"] pipeline: PipelineName,
+    #[description = "comma separated `name=value` inputs, asked for in a modal if not given"]
+    inputs: Option<String>,
 ) -> Result<(), Error> {
     // TODO: add admin check
 
-    let data = match ctx {
-        Context::Prefix(data) => data.data.lock().await,
-        Context::Application(data) => data.data.lock().await,
+    let inputs = match (inputs, ctx) {
+        (Some(inputs), _) => match inputs::parse_pairs(&inputs) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                ctx.reply(format!("invalid inputs, {e}.")).await?;
+                return Ok(());
+            }
+        },
+        (None, Context::Application(ctx)) => match ask_for_inputs(ctx, &pipeline).await? {
+            Some(inputs) => inputs,
+            None => return Ok(()),
+        },
+        (None, Context::Prefix(_)) => Env::new(),
+    };
+
+    // the lock isn't held while the run goes, approvals need it.
+    let (send_cmd, backend) = {
+        let data = match ctx {
+            Context::Prefix(data) => data.data.lock().await,
+            Context::Application(data) => data.data.lock().await,
+        };
+
+        (data.send_cmd.clone(), data.backend.clone())
     };
 
     // let backend = data.backend.lock().await;
     let backend_state = {
-        data.backend.lock().await.state.lock().await.clone()
+        backend.lock().await.state.lock().await.clone()
     };
 
     let response = match backend_state {
-        BackendState::NotConfigured => "must `/load` a repo before running a pipeline.".to_string(),
+        BackendState::NotConfigured => "must `/load` a repo before running a pipeline.".into(),
         BackendState::Available {
             repo: Repo { repo_name: _, url },
         } => {
             let (tx, rx) = unbounded();
 
-            let send_f = move |msg| { if let Err(e) = tx.send(msg) { println!("{e}") } };
-            let send_f: Box<dyn Fn(String) + Send + Sync> = Box::new(send_f);
+            let send_f = move |report| { if let Err(e) = tx.send(report) { println!("{e}") } };
+            let send_f: OnComplete = Box::new(send_f);
 
-            data.send_cmd.send(CiCdCmd::Clone(url))?;
+            send_cmd.send(CiCdCmd::Clone(url))?;
             // data.send_cmd.send(CiCdCmd::RunPipeline(pipeline.clone()))?;
-            data.send_cmd.send(CiCdCmd::RunPipeline { pipeline_name: pipeline.clone(), on_complete: send_f } )?;
+            send_cmd.send(CiCdCmd::RunPipeline {
+                pipeline_name: pipeline.clone(),
+                branch: None,
+                triggered_by: ctx.author().name.clone(),
+                event: RunEvent::Manual,
+                guild_id: ctx.guild_id().map(|id| id.get()),
+                channel_id: Some(ctx.channel_id().get()),
+                rerun: None,
+                inputs,
+                on_complete: send_f,
+            })?;
 
             ctx.reply(format!("started pipline {pipeline}. use `/logs` to get logs.")).await?;
 
             println!("waiting");
 
-            if let Ok(mesg) = rx.recv() {
-                mesg.to_string()
-            } else {
-                "error retrieving logs.".to_string()
-            }
+            wait_for_report(rx).await
         }
         BackendState::RunningPipeline { 
             repo: Repo { repo_name, url: _url },
             pipeline 
-        } => format!("already running {pipeline} from the repository, {repo_name}. a new pipline cant be run until the current pipeline finishes."),
+        } => format!("already running {pipeline} from the repository, {repo_name}. a new pipline cant be run until the current pipeline finishes.").into(),
+    };
+
+    send_report(ctx, response).await?;
+
+    Ok(())
+}
+
+/// queues an earlier run to run again with the same repo, commit and pipeline. returns what to
+/// reply and where the report will be sent.
+fn queue_rerun(
+    data: &Data,
+    rerun: Rerun,
+    triggered_by: String,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+) -> Result<(String, Receiver<RunReport>), String> {
+    let run_id = rerun.run_id;
+    let record = history::load(run_id).map_err(|e| format!("{e}."))?;
+
+    if rerun.failed_only && record.outcome == Outcome::Passed {
+        return Err(format!("run {run_id} passed, nothing failed to re-run."));
+    }
+
+    let Some(url) = data.git_links.get(&record.repo) else {
+        return Err(format!(
+            "unknown git repo {}. try: `/show Repos`",
+            record.repo
+        ));
+    };
+
+    let (tx, rx) = unbounded();
+    let queued = QueuedRun {
+        repo: Repo {
+            repo_name: record.repo.clone(),
+            url: url.clone(),
+        },
+        branch: record.branch.clone(),
+        commit: record.commit_sha.clone(),
+        pipeline_name: record.pipeline.clone(),
+        guild_id,
+        channel_id: Some(channel_id),
+        triggered_by,
+        event: record.event.unwrap_or(RunEvent::Manual),
+        rerun: Some(rerun),
+        inputs: record.inputs,
+        report: Some(tx),
+    };
+
+    if let Err(e) = data.send_cmd.send(CiCdCmd::Enqueue(queued)) {
+        return Err(format!("failed to queue the re-run. {e}"));
+    }
+
+    let what = if rerun.failed_only {
+        "the failed jobs of run"
+    } else {
+        "run"
+    };
+
+    Ok((
+        format!(
+            "queued {what} {run_id} ({} on {}@{:.8}). it starts once the backend is free.",
+            record.pipeline, record.branch, record.commit_sha
+        ),
+        rx,
+    ))
+}
+
+/// waits for a queued runs report without blocking the runtime.
+async fn wait_for_report(rx: Receiver<RunReport>) -> RunReport {
+    match spawn_blocking(move || rx.recv()).await {
+        Ok(Ok(report)) => report,
+        _ => "error retrieving logs.".into(),
+    }
+}
+
+/// runs an earlier run again, at the same commit.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn rerun(
+    ctx: Context<'_>,
+    #[description = "the run to re-run"] run_id: RunId,
+    #[description = "only re-run failed jobs, reusing the artifacts of passed ones"]
+    failed_only: Option<bool>,
+) -> Result<(), Error> {
+    let rerun = Rerun {
+        run_id,
+        failed_only: failed_only.unwrap_or(false),
+    };
+    let queued = {
+        let data = match ctx {
+            Context::Prefix(data) => data.data.lock().await,
+            Context::Application(data) => data.data.lock().await,
+        };
+
+        queue_rerun(
+            &data,
+            rerun,
+            ctx.author().name.clone(),
+            ctx.guild_id().map(|id| id.get()),
+            ctx.channel_id().get(),
+        )
+    };
+
+    match queued {
+        Ok((response, rx)) => {
+            ctx.reply(response).await?;
+            send_report(ctx, wait_for_report(rx).await).await?;
+        }
+        Err(response) => {
+            ctx.reply(response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// handles the buttons on run reports and approval requests.
+pub async fn event_handler(
+    ctx: &serenity::Context,
+    event: &serenity::FullEvent,
+    _framework: poise::FrameworkContext<'_, Arc<Mutex<Data>>, Error>,
+    data: &Arc<Mutex<Data>>,
+) -> Result<(), Error> {
+    let serenity::FullEvent::InteractionCreate {
+        interaction: serenity::Interaction::Component(interaction),
+    } = event
+    else {
+        return Ok(());
+    };
+
+    let Some((button, id)) = interaction.data.custom_id.split_once(':') else {
+        return Ok(());
+    };
+
+    match (button, id.parse()) {
+        (RERUN_BUTTON, Ok(run_id)) => rerun_button(ctx, interaction, data, run_id, false).await,
+        (RERUN_FAILED_BUTTON, Ok(run_id)) => {
+            rerun_button(ctx, interaction, data, run_id, true).await
+        }
+        (APPROVE_BUTTON, _) => decide(ctx, interaction, data, id, true).await,
+        (REJECT_BUTTON, _) => decide(ctx, interaction, data, id, false).await,
+        _ => Ok(()),
+    }
+}
+
+/// replies to a button press with a message only the presser sees.
+async fn reply_ephemeral(
+    ctx: &serenity::Context,
+    interaction: &ComponentInteraction,
+    response: String,
+) -> Result<(), Error> {
+    let message = CreateInteractionResponseMessage::new()
+        .content(response)
+        .ephemeral(true);
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Message(message))
+        .await?;
+
+    Ok(())
+}
+
+async fn rerun_button(
+    ctx: &serenity::Context,
+    interaction: &ComponentInteraction,
+    data: &Arc<Mutex<Data>>,
+    run_id: RunId,
+    failed_only: bool,
+) -> Result<(), Error> {
+    // the same as `/rerun`. buttons don't go through poise, so it's checked here.
+    let is_admin = interaction
+        .member
+        .as_ref()
+        .and_then(|member| member.permissions)
+        .is_some_and(|permissions| permissions.administrator());
+
+    if !is_admin {
+        let response = "only server admins can re-run pipelines.".to_string();
+
+        return reply_ephemeral(ctx, interaction, response).await;
+    }
+
+    let queued = queue_rerun(
+        &*data.lock().await,
+        Rerun {
+            run_id,
+            failed_only,
+        },
+        interaction.user.name.clone(),
+        interaction.guild_id.map(|id| id.get()),
+        interaction.channel_id.get(),
+    );
+    let (response, rx) = match queued {
+        Ok(queued) => queued,
+        Err(response) => return reply_ephemeral(ctx, interaction, response).await,
+    };
+
+    let message = CreateInteractionResponseMessage::new().content(response);
+    interaction
+        .create_response(ctx, CreateInteractionResponse::Message(message))
+        .await?;
+
+    // interaction tokens expire long before most runs finish, so the report is a new message.
+    let report = wait_for_report(rx).await;
+    let mut embeds = summary_embeds(&report).into_iter();
+    let mut message = CreateMessage::new()
+        .components(rerun_buttons(&report))
+        .add_files(report_attachments(&report).await)
+        .content(report.msg);
+
+    if let Some(embed) = embeds.next() {
+        message = message.embed(embed);
+    }
+
+    interaction.channel_id.send_message(ctx, message).await?;
+
+    for embed in embeds {
+        let message = CreateMessage::new().embed(embed);
+        interaction.channel_id.send_message(ctx, message).await?;
+    }
+
+    Ok(())
+}
+
+/// an approval request posted to discord.
+#[derive(Debug, Clone)]
+pub struct PendingApproval {
+    pub request: ApprovalRequest,
+    /// the message with the approval buttons.
+    pub message_id: u64,
+}
+
+/// pending approvals by `ApprovalRequest::key`.
+pub type Approvals = Arc<Mutex<HashMap<String, PendingApproval>>>;
+
+/// what an approval request says. `decided` replaces the deadline once it's decided.
+fn approval_message(request: &ApprovalRequest, decided: Option<&str>) -> String {
+    let mut msg = format!(
+        "run {} of {} on {}@{:.8}, started by {}, is waiting for approval to run past `{}`.",
+        request.run_id,
+        request.repo,
+        request.branch,
+        request.commit_sha,
+        request.triggered_by,
+        request.job
+    );
+
+    if let Some(message) = &request.approval.message {
+        msg.push_str(&format!("\n{message}"));
+    }
+
+    if let Some(roles) = &request.approval.roles {
+        msg.push_str(&format!("\nonly {} can decide.", roles.join(", ")));
+    }
+
+    match decided {
+        Some(decided) => msg.push_str(&format!("\n{decided}")),
+        None => msg.push_str(&format!(
+            "\nrejected <t:{}:R> if nobody decides.",
+            request.deadline
+        )),
+    }
+
+    msg
+}
+
+/// the ids and names of the roles of whoever pressed a button.
+async fn member_roles(
+    ctx: &serenity::Context,
+    interaction: &ComponentInteraction,
+) -> Vec<(String, String)> {
+    let (Some(member), Some(guild_id)) = (&interaction.member, interaction.guild_id) else {
+        return Vec::new();
+    };
+    let names = guild_id.roles(ctx).await.unwrap_or_default();
+
+    member
+        .roles
+        .iter()
+        .map(|id| {
+            let name = names.get(id).map(|role| role.name.clone());
+
+            (id.get().to_string(), name.unwrap_or_default())
+        })
+        .collect()
+}
+
+/// approves or rejects a pending approval, if the presser has one of its roles.
+async fn decide(
+    ctx: &serenity::Context,
+    interaction: &ComponentInteraction,
+    data: &Arc<Mutex<Data>>,
+    key: &str,
+    approved: bool,
+) -> Result<(), Error> {
+    let approvals = data.lock().await.approvals.clone();
+    let Some(request) = approvals
+        .lock()
+        .await
+        .get(key)
+        .map(|pending| pending.request.clone())
+    else {
+        return reply_ephemeral(ctx, interaction, NOT_PENDING.into()).await;
+    };
+
+    let member_roles = member_roles(ctx, interaction).await;
+
+    if !request.approval.allows(&member_roles) {
+        let roles = request.approval.roles.clone().unwrap_or_default();
+        let response = format!("only {} can decide.", roles.join(", "));
+
+        return reply_ephemeral(ctx, interaction, response).await;
+    }
+
+    // whoever gets here first decides.
+    if approvals.lock().await.remove(key).is_none() {
+        return reply_ephemeral(ctx, interaction, NOT_PENDING.into()).await;
+    }
+
+    let by = interaction.user.name.clone();
+    let decided = if request
+        .decision
+        .send(Decision {
+            approved,
+            by: by.clone(),
+        })
+        .is_err()
+    {
+        "nobody decided in time, rejected.".to_string()
+    } else if approved {
+        format!("approved by {by}.")
+    } else {
+        format!("rejected by {by}.")
+    };
+
+    let message = CreateInteractionResponseMessage::new()
+        .content(approval_message(&request, Some(&decided)))
+        .components(Vec::new());
+    interaction
+        .create_response(ctx, CreateInteractionResponse::UpdateMessage(message))
+        .await?;
+
+    Ok(())
+}
+
+/// posts the approval requests of runs and marks them rejected once they time out. the run
+/// rejects timed out approvals by itself.
+pub async fn run_approvals(
+    requests: Receiver<ApprovalRequest>,
+    http: Arc<serenity::Http>,
+    approvals: Approvals,
+) {
+    loop {
+        sleep(Duration::from_millis(500)).await;
+
+        while let Ok(request) = requests.try_recv() {
+            let key = request.key();
+            let buttons = vec![
+                CreateButton::new(format!("{APPROVE_BUTTON}:{key}"))
+                    .label("Approve")
+                    .style(ButtonStyle::Success),
+                CreateButton::new(format!("{REJECT_BUTTON}:{key}"))
+                    .label("Reject")
+                    .style(ButtonStyle::Danger),
+            ];
+            let message = CreateMessage::new()
+                .content(approval_message(&request, None))
+                .components(vec![CreateActionRow::Buttons(buttons)]);
+
+            match serenity::ChannelId::new(request.channel_id)
+                .send_message(&http, message)
+                .await
+            {
+                Ok(message) => {
+                    let pending = PendingApproval {
+                        request,
+                        message_id: message.id.get(),
+                    };
+                    approvals.lock().await.insert(key, pending);
+                }
+                // dropping the request fails the approval job.
+                Err(e) => eprintln!("failed to ask for approval of {key}. {e}"),
+            }
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let expired: Vec<PendingApproval> = {
+            let mut approvals = approvals.lock().await;
+            let keys: Vec<String> = approvals
+                .iter()
+                .filter(|(_, pending)| pending.request.deadline <= now)
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            keys.iter()
+                .filter_map(|key| approvals.remove(key))
+                .collect()
+        };
+
+        for pending in expired {
+            let message = EditMessage::new()
+                .content(approval_message(
+                    &pending.request,
+                    Some("nobody decided in time, rejected."),
+                ))
+                .components(Vec::new());
+
+            if let Err(e) = serenity::ChannelId::new(pending.request.channel_id)
+                .edit_message(&http, pending.message_id, message)
+                .await
+            {
+                eprintln!("failed to mark {} as timed out. {e}", pending.request.key());
+            }
+        }
+    }
+}
+
+/// polls a registered repo for new commits. for remotes that can't send webhooks.
+#[poise::command(slash_command, prefix_command)]
+pub async fn poll(
+    ctx: Context<'_>,
+    #[description = "poll this repo"] repo: RepoName,
+    #[description = "seconds between polls, 0 stops polling"] interval: u64,
+    #[description = "comma separated branches to watch (default: main)"] branches: Option<String>,
+) -> Result<(), Error> {
+    // TODO: add admin check
+
+    let data = match ctx {
+        Context::Prefix(data) => data.data.lock().await,
+        Context::Application(data) => data.data.lock().await,
+    };
+
+    let response = match data.git_links.get(&repo) {
+        Some(url) => {
+            let mut poller = data.poller.lock().await;
+
+            let response = if interval == 0 {
+                poller.repos.remove(&repo);
+                format!("stopped polling {repo}.")
+            } else {
+                let branches: Vec<String> = branches
+                    .unwrap_or("main".into())
+                    .split(',')
+                    .map(|branch| branch.trim().to_string())
+                    .filter(|branch| !branch.is_empty())
+                    .collect();
+                let response = format!("polling {branches:?} of {repo} every {interval}s.");
+                poller.watch(
+                    repo,
+                    url.clone(),
+                    interval,
+                    branches,
+                    ctx.guild_id().map(|id| id.get()),
+                    Some(ctx.channel_id().get()),
+                );
+
+                response
+            };
+
+            if let Err(e) = poller.save() {
+                eprintln!("failed to save poll state: {e}");
+            }
+
+            response
+        }
+        None => format!("unknown git repo {repo}. try: `/show Repos`"),
     };
 
     ctx.reply(response).await?;
@@ -233,3 +1020,676 @@ pub async fn run(
     Ok(())
 }
 
+/// resolves the scope a `/secret` command acts on.
+async fn secret_scope(
+    ctx: Context<'_>,
+    scope: SecretScopeArgs,
+    repo: Option<RepoName>,
+) -> Result<SecretScope, String> {
+    match scope {
+        SecretScopeArgs::Repo => {
+            let Some(repo) = repo else {
+                return Err("a repo is required for repo secrets.".into());
+            };
+
+            if ctx.data().lock().await.git_links.contains_key(&repo) {
+                Ok(SecretScope::Repo(repo))
+            } else {
+                Err(format!("unknown git repo {repo}. try: `/show Repos`"))
+            }
+        }
+        SecretScopeArgs::Guild => ctx
+            .guild_id()
+            .map(|id| SecretScope::Guild(id.get()))
+            .ok_or("guild secrets can only be managed from a guild.".into()),
+    }
+}
+
+/// manages secrets given to pipelines as environment variables.
+#[poise::command(
+    slash_command,
+    subcommands("secret_set", "secret_list", "secret_remove"),
+    ephemeral
+)]
+pub async fn secret(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// sets a secret. the value is entered in a private modal so it never shows up in chat.
+#[poise::command(slash_command, rename = "set", ephemeral)]
+pub async fn secret_set(
+    ctx: ApplicationContext<'_>,
+    #[description = "repo or guild wide"] scope: SecretScopeArgs,
+    #[description = "the repo, for repo secrets"] repo: Option<RepoName>,
+) -> Result<(), Error> {
+    // TODO: add admin check
+
+    let scope = match secret_scope(ctx.into(), scope, repo).await {
+        Ok(scope) => scope,
+        Err(response) => {
+            ctx.reply(response).await?;
+            return Ok(());
+        }
+    };
+
+    let Some(SecretModal { name, value }) = poise::execute_modal(ctx, None, None).await? else {
+        return Ok(());
+    };
+    let name = name.trim().to_string();
+
+    let response = if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        format!("{name:?} is not a valid environment variable name.")
+    } else {
+        match SecretStore::load().and_then(|mut store| store.set(&scope, &name, &value)) {
+            Ok(()) => format!("secret {name} saved."),
+            Err(e) => format!("failed to save secret. {e}"),
+        }
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// lists secret names. values are never shown.
+#[poise::command(slash_command, rename = "list", ephemeral)]
+pub async fn secret_list(
+    ctx: Context<'_>,
+    #[description = "repo or guild wide"] scope: SecretScopeArgs,
+    #[description = "the repo, for repo secrets"] repo: Option<RepoName>,
+) -> Result<(), Error> {
+    let response = match secret_scope(ctx, scope, repo).await {
+        Ok(scope) => match SecretStore::load() {
+            Ok(store) => format!("{:?}", store.names(&scope)),
+            Err(e) => format!("failed to load secrets. {e}"),
+        },
+        Err(response) => response,
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// removes a secret.
+#[poise::command(slash_command, rename = "remove", ephemeral)]
+pub async fn secret_remove(
+    ctx: Context<'_>,
+    #[description = "the secret to remove"] name: String,
+    #[description = "repo or guild wide"] scope: SecretScopeArgs,
+    #[description = "the repo, for repo secrets"] repo: Option<RepoName>,
+) -> Result<(), Error> {
+    // TODO: add admin check
+
+    let response = match secret_scope(ctx, scope, repo).await {
+        Ok(scope) => match SecretStore::load().and_then(|mut store| store.remove(&scope, &name)) {
+            Ok(true) => format!("removed secret {name}."),
+            Ok(false) => format!("no secret named {name}."),
+            Err(e) => format!("failed to remove secret. {e}"),
+        },
+        Err(response) => response,
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// lists a run's artifacts and where to download them.
+#[poise::command(slash_command, prefix_command)]
+pub async fn artifacts(
+    ctx: Context<'_>,
+    #[description = "the run to list artifacts of"] run_id: RunId,
+) -> Result<(), Error> {
+    let response = match artifacts::list(run_id) {
+        Ok(list) if list.is_empty() => format!("run {run_id} has no artifacts."),
+        Ok(list) => {
+            let base_url = std::env::var(ARTIFACT_URL_VAR).ok();
+            let lines: Vec<String> = list
+                .iter()
+                .map(|artifact| {
+                    let path = artifact.stored_path();
+                    let path = path.to_string_lossy();
+                    let link = match &base_url {
+                        Some(base) => format!(
+                            " <{}/artifacts/{run_id}/{path}>",
+                            base.trim_end_matches('/')
+                        ),
+                        None => String::new(),
+                    };
+
+                    format!(
+                        "`{path}` ({}, sha256 `{}`){link}",
+                        artifacts::fmt_size(artifact.size),
+                        artifact.sha256
+                    )
+                })
+                .collect();
+
+            lines.join("\n")
+        }
+        Err(e) => format!("{e}."),
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// manages dependency caches.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    subcommands("cache_list", "cache_clear")
+)]
+pub async fn cache(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// lists a repo's caches.
+#[poise::command(slash_command, prefix_command, rename = "list")]
+pub async fn cache_list(
+    ctx: Context<'_>,
+    #[description = "the repo to list caches of"] repo: RepoName,
+) -> Result<(), Error> {
+    let entries = cache::list(&repo);
+
+    let response = if entries.is_empty() {
+        format!("{repo} has no caches.")
+    } else {
+        entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "`{}` ({}, last used <t:{}:R>)",
+                    entry.key,
+                    artifacts::fmt_size(entry.size),
+                    entry.last_used
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// deletes a repo's caches.
+#[poise::command(slash_command, prefix_command, rename = "clear")]
+pub async fn cache_clear(
+    ctx: Context<'_>,
+    #[description = "the repo to clear caches of"] repo: RepoName,
+) -> Result<(), Error> {
+    // TODO: add admin check
+
+    let response = match cache::clear(&repo) {
+        Ok(n) => format!("cleared {n} caches of {repo}."),
+        Err(e) => format!("failed to clear caches. {e}"),
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// rebuilds the runner images from scratch on the next run. pulls new base images.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn rebuild_runner(ctx: Context<'_>) -> Result<(), Error> {
+    let data = match ctx {
+        Context::Prefix(data) => data.data.lock().await,
+        Context::Application(data) => data.data.lock().await,
+    };
+
+    let response = match data.send_cmd.send(CiCdCmd::RebuildRunner) {
+        Ok(()) => "the runner images will be rebuilt on the next run.".to_string(),
+        Err(e) => format!("failed to request a rebuild. {e}"),
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// sends the logs of a service container from a run.
+#[poise::command(slash_command, prefix_command)]
+pub async fn service_logs(
+    ctx: Context<'_>,
+    #[description = "the run the service was started for"] run_id: RunId,
+    #[description = "the service name"] service: String,
+    #[description = "the job, if more than one ran the service"] job: Option<PipelineName>,
+) -> Result<(), Error> {
+    let report = match services::logs(run_id, &service, job.as_deref()) {
+        Ok(path) => RunReport {
+            msg: format!("logs of {service} from run {run_id}."),
+            attachments: vec![path],
+            ..Default::default()
+        },
+        Err(e) => e.to_string().into(),
+    };
+
+    send_report(ctx, report).await?;
+
+    Ok(())
+}
+
+/// lists recent pipeline runs.
+#[poise::command(slash_command, prefix_command)]
+pub async fn history(
+    ctx: Context<'_>,
+    #[description = "only runs of this repo"] repo: Option<RepoName>,
+    #[description = "how many runs to list (default: 10)"] count: Option<usize>,
+) -> Result<(), Error> {
+    let runs = history::recent(repo.as_deref(), count.unwrap_or(10));
+
+    let response = if runs.is_empty() {
+        "no runs yet.".to_string()
+    } else {
+        runs.iter()
+            .map(|run| {
+                let rerun_of = match run.rerun_of {
+                    Some(run_id) => format!(", re-run of `{run_id}`"),
+                    None => String::new(),
+                };
+
+                format!(
+                    "`{}` {} of {} on {}@{:.8}: {} in {}, by {}{rerun_of} <t:{}:R>",
+                    run.run_id,
+                    run.pipeline,
+                    run.repo,
+                    run.branch,
+                    run.commit_sha,
+                    run.outcome,
+                    events::fmt_duration(run.duration_ms),
+                    run.triggered_by,
+                    run.started_at
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// a deployment as listed by `/environments`.
+fn fmt_deployment(deployment: &Deployment) -> String {
+    format!(
+        "{}@{:.8} by {}, run `{}` of {} <t:{}:R>",
+        deployment.branch,
+        deployment.commit_sha,
+        deployment.triggered_by,
+        deployment.run_id,
+        deployment.job,
+        deployment.deployed_at
+    )
+}
+
+fn fmt_environment(name: &str, environment: &Environment) -> String {
+    let mut lines = vec![match environment.current() {
+        Some(current) => format!("- `{name}`: {}", fmt_deployment(current)),
+        None => format!("- `{name}`: nothing deployed yet."),
+    }];
+
+    if let Some(target) = environment.rollback_target() {
+        lines.push(format!("  rolls back to {}", fmt_deployment(target)));
+    }
+
+    let Protection { branches, approval } = &environment.protection;
+
+    if !branches.is_empty() {
+        lines.push(format!("  only deployed from {}", branches.join(", ")));
+    }
+
+    if let Some(approval) = approval {
+        let by = match &approval.roles {
+            Some(roles) => roles.join(", "),
+            None => "anyone".to_string(),
+        };
+        let timeout = events::fmt_duration(approval.timeout().as_millis() as u64);
+        lines.push(format!("  needs approval by {by} within {timeout}"));
+    }
+
+    lines.join("\n")
+}
+
+/// lists the environments pipelines deploy to and what's deployed in them.
+#[poise::command(slash_command, prefix_command)]
+pub async fn environments(
+    ctx: Context<'_>,
+    #[description = "only environments of this repo"] repo: Option<RepoName>,
+) -> Result<(), Error> {
+    let environments = Environments::load()?;
+    let response = environments
+        .repos
+        .iter()
+        .filter(|(name, _)| repo.as_ref().is_none_or(|repo| repo == *name))
+        .map(|(name, repo_environments)| {
+            let listed = repo_environments
+                .iter()
+                .map(|(name, environment)| fmt_environment(name, environment))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!("**{name}**\n{listed}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if response.is_empty() {
+        ctx.reply("no environments yet. pipelines add theirs with `environment`.")
+            .await?;
+    } else {
+        ctx.reply(response).await?;
+    }
+
+    Ok(())
+}
+
+/// sets who and what can deploy to an environment. without any rules it's unprotected.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn protect_environment(
+    ctx: Context<'_>,
+    #[description = "the repo the environment belongs to"] repo: RepoName,
+    #[description = "the environment to protect"] environment: String,
+    #[description = "comma separated branches allowed to deploy, globs like `release/*` work"]
+    branches: Option<String>,
+    #[description = "comma separated names or ids of roles that must approve every deployment"]
+    approvers: Option<String>,
+    #[description = "seconds to wait for approval before rejecting (default: a day)"]
+    timeout: Option<u64>,
+) -> Result<(), Error> {
+    let split = |list: Option<String>| -> Vec<String> {
+        list.iter()
+            .flat_map(|list| list.split(','))
+            .map(|item| item.trim().to_string())
+            .filter(|item| !item.is_empty())
+            .collect()
+    };
+    let branches = split(branches);
+    let approvers = split(approvers);
+    let approval = (!approvers.is_empty() || timeout.is_some()).then(|| Approval {
+        roles: (!approvers.is_empty()).then_some(approvers),
+        timeout,
+        message: Some(format!("deploy to {environment}")),
+    });
+
+    let mut environments = Environments::load()?;
+    let protected = environments.get_mut(&repo, &environment);
+    protected.protection = Protection { branches, approval };
+    let response = format!(
+        "updated the rules of {repo}'s environment.\n{}",
+        fmt_environment(&environment, protected)
+    );
+    environments.save()?;
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// deploys the previous deployment of an environment again, by re-running its deploy job at its
+/// commit.
+#[poise::command(
+    slash_command,
+    prefix_command,
+    guild_only,
+    required_permissions = "ADMINISTRATOR"
+)]
+pub async fn rollback(
+    ctx: Context<'_>,
+    #[description = "the repo the environment belongs to"] repo: RepoName,
+    #[description = "the environment to roll back"] environment: String,
+) -> Result<(), Error> {
+    let queued = {
+        let data = match ctx {
+            Context::Prefix(data) => data.data.lock().await,
+            Context::Application(data) => data.data.lock().await,
+        };
+
+        queue_rollback(
+            &data,
+            &repo,
+            &environment,
+            ctx.author().name.clone(),
+            ctx.guild_id().map(|id| id.get()),
+            ctx.channel_id().get(),
+        )
+    };
+
+    match queued {
+        Ok((response, rx, rolled_back)) => {
+            ctx.reply(response).await?;
+            let report = wait_for_report(rx).await;
+
+            // only once the rollback deployed, a failed one leaves the deployment current.
+            if report.outcome == Some(Outcome::Passed) {
+                if let Err(e) = environments::mark_rolled_back(&repo, &environment, rolled_back) {
+                    eprintln!("failed to mark the deployment to {environment} as rolled back. {e}");
+                }
+            }
+
+            send_report(ctx, report).await?;
+        }
+        Err(response) => {
+            ctx.reply(response).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// queues the deploy job of the deployment before the current one of an environment. returns what
+/// to reply, where the report will be sent and the run id of the deployment being rolled back.
+fn queue_rollback(
+    data: &Data,
+    repo: &str,
+    environment: &str,
+    triggered_by: String,
+    guild_id: Option<GuildId>,
+    channel_id: ChannelId,
+) -> Result<(String, Receiver<RunReport>, RunId), String> {
+    let environments = Environments::load().map_err(|e| format!("{e}."))?;
+
+    let Some((current, target)) = environments.get(repo, environment).and_then(|environment| {
+        Some((
+            environment.current()?.clone(),
+            environment.rollback_target()?.clone(),
+        ))
+    }) else {
+        return Err(format!(
+            "{repo} has no earlier deployment to {environment} to roll back to."
+        ));
+    };
+
+    let Some(url) = data.git_links.get(repo) else {
+        return Err(format!("unknown git repo {repo}. try: `/show Repos`"));
+    };
+
+    // the deploy job gets the inputs it deployed with, if the run was started from it.
+    let inputs = match history::load(target.run_id) {
+        Ok(record) if record.pipeline == target.job => record.inputs,
+        _ => Env::new(),
+    };
+
+    let (tx, rx) = unbounded();
+    let queued = QueuedRun {
+        repo: Repo {
+            repo_name: repo.to_string(),
+            url: url.clone(),
+        },
+        branch: target.branch.clone(),
+        commit: target.commit_sha.clone(),
+        pipeline_name: target.job.clone(),
+        guild_id,
+        channel_id: Some(channel_id),
+        triggered_by,
+        event: RunEvent::Manual,
+        rerun: None,
+        inputs,
+        report: Some(tx),
+    };
+
+    if let Err(e) = data.send_cmd.send(CiCdCmd::Enqueue(queued)) {
+        return Err(format!("failed to queue the rollback. {e}"));
+    }
+
+    Ok((
+        format!(
+            "rolling {environment} back to {}@{:.8} from run {}. {} starts once the backend is free.",
+            target.branch, target.commit_sha, target.run_id, target.job
+        ),
+        rx,
+        current.run_id,
+    ))
+}
+
+/// checks a pipeline file, the attached one or the loaded repos.
+#[poise::command(slash_command, prefix_command)]
+pub async fn validate(
+    ctx: Context<'_>,
+    #[description = "the file to check (default: the loaded repos)"] file: Option<Attachment>,
+) -> Result<(), Error> {
+    let (repos, backend_state) = {
+        let data = match ctx {
+            Context::Prefix(data) => data.data.lock().await,
+            Context::Application(data) => data.data.lock().await,
+        };
+        let backend_state = { data.backend.lock().await.state.lock().await.clone() };
+
+        (data.git_links.clone(), backend_state)
+    };
+
+    let (file, source) = match file {
+        Some(file) => (
+            file.filename.clone(),
+            String::from_utf8_lossy(&file.download().await?).to_string(),
+        ),
+        None => {
+            if backend_state == BackendState::NotConfigured {
+                ctx.reply("must `/load` a repo or attach a file to validate.")
+                    .await?;
+                return Ok(());
+            }
+
+            match ci_cd::read_pipeline_file(Path::new(CACHE_DIR)) {
+                Ok((file, source)) => (file.to_string(), source),
+                Err(e) => {
+                    ctx.reply(e.to_string()).await?;
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    // local includes of an attached file are read from the loaded repo.
+    let diagnostics = {
+        let includes = Includes::from_dir(Path::new(CACHE_DIR), repos);
+        lint::lint(&file, &source, &includes)
+    };
+
+    let response = if diagnostics.is_empty() {
+        format!("{file} is valid.")
+    } else {
+        format!("{file}:\n{}", lint::discord_report(&diagnostics))
+    };
+
+    ctx.reply(response).await?;
+
+    Ok(())
+}
+
+/// inspects the loaded repos pipelines.
+#[poise::command(slash_command, prefix_command, subcommands("pipeline_expand"))]
+pub async fn pipeline(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// shows the loaded repos pipelines with includes and templates resolved.
+#[poise::command(slash_command, prefix_command, rename = "expand")]
+pub async fn pipeline_expand(
+    ctx: Context<'_>,
+    #[description = "the pipeline to show (default: all of them)"] name: Option<PipelineName>,
+) -> Result<(), Error> {
+    let (repos, backend_state) = {
+        let data = match ctx {
+            Context::Prefix(data) => data.data.lock().await,
+            Context::Application(data) => data.data.lock().await,
+        };
+        let backend_state = { data.backend.lock().await.state.lock().await.clone() };
+
+        (data.git_links.clone(), backend_state)
+    };
+
+    if backend_state == BackendState::NotConfigured {
+        ctx.reply("must `/load` a repo first.").await?;
+        return Ok(());
+    }
+
+    let (file, source) = match ci_cd::read_pipeline_file(Path::new(CACHE_DIR)) {
+        Ok(file) => file,
+        Err(e) => {
+            ctx.reply(e.to_string()).await?;
+            return Ok(());
+        }
+    };
+
+    let loaded = {
+        let includes = Includes::from_dir(Path::new(CACHE_DIR), repos);
+        lint::load(file, &source, &includes)
+    };
+    let pipelines = match loaded {
+        Ok((pipelines, _)) => pipelines,
+        Err(diagnostics) => {
+            ctx.reply(format!(
+                "{file} is invalid:\n{}",
+                lint::discord_report(&diagnostics)
+            ))
+            .await?;
+            return Ok(());
+        }
+    };
+
+    let pipelines: BTreeMap<_, _> = match name {
+        Some(name) => match pipelines.get(&name) {
+            Some(pipeline) => [(name, pipeline.clone())].into(),
+            None => {
+                ctx.reply(format!("unknown pipeline: {name}")).await?;
+                return Ok(());
+            }
+        },
+        None => pipelines.into_iter().collect(),
+    };
+    let expanded = toml::to_string_pretty(&pipelines)?;
+    let response = format!("```toml\n{expanded}```");
+
+    // too long for a message, discord allows 2000 chars.
+    let reply = if response.chars().count() <= 2000 {
+        poise::CreateReply::default().content(response)
+    } else {
+        poise::CreateReply::default()
+            .content(format!("{file} expanded:"))
+            .attachment(CreateAttachment::bytes(expanded, "expanded.toml"))
+    };
+
+    ctx.send(reply).await?;
+
+    Ok(())
+}
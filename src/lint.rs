@@ -0,0 +1,1271 @@
+use crate::{
+    ci_cd::{job_order, PipelineFile, Pipelines},
+    document::{self, Format, Node, NodeValue},
+    expr::Expr,
+    include::{Include, Includes, Origin},
+    inputs::InputKind,
+};
+use schemars::schema_for;
+use serde_json::Value as Json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+/// top level keys that aren't pipelines.
+pub const RESERVED_KEYS: [&str; 2] = ["include", "templates"];
+/// pipeline keys that only matter to jobs running steps.
+const STEP_KEYS: [&str; 9] = [
+    "container",
+    "script",
+    "on_failure",
+    "always",
+    "matrix",
+    "services",
+    "cache",
+    "artifacts",
+    "retry",
+];
+
+/// diagnostics listed in a discord message before the rest are cut off.
+pub const DISCORD_DIAGNOSTICS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    /// the pipeline still runs, e.g. a key dcicd doesn't know is ignored.
+    Warning,
+}
+
+/// a problem with a pipeline file and where it is.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Diagnostic {
+    /// the pipeline file or a file it includes.
+    pub file: String,
+    /// numbered from 1.
+    pub line: usize,
+    /// numbered from 1, in chars.
+    pub column: usize,
+    pub severity: Severity,
+    /// the key the problem is at, e.g. `build.script[1].run`. empty for the whole file.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+
+        write!(
+            f,
+            "{}:{}:{}: {severity}: ",
+            self.file, self.line, self.column
+        )?;
+
+        match self.path.as_str() {
+            "" => write!(f, "{}", self.message),
+            path => write!(f, "{path}: {}", self.message),
+        }
+    }
+}
+
+/// the json schema of the pipeline file, for editors.
+pub fn schema() -> Json {
+    schema_for!(PipelineFile).to_value()
+}
+
+/// checks a pipeline file and the files it includes. errors first, then warnings, each in file
+/// order.
+pub fn lint(file: &str, source: &str, includes: &Includes) -> Vec<Diagnostic> {
+    check(file, source, includes).1
+}
+
+/// reads a pipeline file, resolving includes and `extends`. fails with every diagnostic if it has
+/// errors, warnings are returned with the pipelines.
+pub fn load(
+    file: &str,
+    source: &str,
+    includes: &Includes,
+) -> Result<(Pipelines, Vec<Diagnostic>), Vec<Diagnostic>> {
+    match check(file, source, includes) {
+        (Some(pipelines), diagnostics) => Ok((pipelines, diagnostics)),
+        (None, diagnostics) => Err(diagnostics),
+    }
+}
+
+/// the diagnostics as one message.
+pub fn report(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// the diagnostics as a code block for discord, cut short if there are many.
+pub fn discord_report(diagnostics: &[Diagnostic]) -> String {
+    let mut report = report(&diagnostics[..diagnostics.len().min(DISCORD_DIAGNOSTICS)]);
+
+    if diagnostics.len() > DISCORD_DIAGNOSTICS {
+        report.push_str(&format!(
+            "\n... and {} more",
+            diagnostics.len() - DISCORD_DIAGNOSTICS
+        ));
+    }
+
+    format!("```\n{report}\n```")
+}
+
+fn check(file: &str, source: &str, includes: &Includes) -> (Option<Pipelines>, Vec<Diagnostic>) {
+    let mut linter = Linter::default();
+
+    let Some(mut root) = linter.parse(file, source, Format::of(file)) else {
+        return (None, linter.finish());
+    };
+
+    linter.include(
+        &mut root,
+        &Origin::Local,
+        includes,
+        &mut vec![(Origin::Local, normalize(file))],
+    );
+    linter.extend(&mut root);
+
+    let schema = schema();
+    linter.schema(&root, &schema, &schema, "", 0);
+
+    let pipelines = if linter.has_errors() {
+        None
+    } else {
+        // anything the schema can't tell.
+        let mut pipelines = root.to_json();
+
+        if let Some(pipelines) = pipelines.as_object_mut() {
+            for key in RESERVED_KEYS {
+                pipelines.remove(key);
+            }
+        }
+
+        match serde_json::from_value(pipelines) {
+            Ok(pipelines) => Some(pipelines),
+            Err(e) => {
+                linter.error(0, "", &e.to_string());
+                None
+            }
+        }
+    };
+
+    if let Some(pipelines) = &pipelines {
+        linter.references(&root, pipelines);
+    }
+
+    let pipelines = pipelines.filter(|_| !linter.has_errors());
+
+    (pipelines, linter.finish())
+}
+
+/// every string under the node with where it is.
+fn strings<'a>(node: &'a Node, path: &str, found: &mut Vec<(String, usize, &'a str)>) {
+    if let (Some(s), Some(at)) = (node.as_str(), node.at) {
+        found.push((path.to_string(), at, s));
+    }
+
+    for (key, _, node) in node.entries() {
+        strings(node, &join(path, key), found);
+    }
+
+    for (i, node) in node.elements().iter().enumerate() {
+        strings(node, &format!("{path}[{i}]"), found);
+    }
+}
+
+/// `path.key`, quoting keys that aren't bare.
+fn join(path: &str, key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    let key = if bare {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    };
+
+    match path {
+        "" => key,
+        _ => format!("{path}.{key}"),
+    }
+}
+
+/// what a json type is called in toml.
+fn toml_kind(kind: &str) -> &str {
+    match kind {
+        "object" => "a table",
+        "array" => "an array",
+        "string" => "a string",
+        "integer" => "an integer",
+        "number" => "a number",
+        "boolean" => "a boolean",
+        "datetime" => "a datetime",
+        kind => kind,
+    }
+}
+
+/// how many single char edits turn `a` into `b`.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = (prev + usize::from(ca != *cb)).min(row[j] + 1).min(cur + 1);
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// a file diagnostics can point into.
+struct Source {
+    name: String,
+    text: String,
+    /// where the files offsets start. every file gets its own range so nodes copied between
+    /// files by includes and `extends` still point to where they were written.
+    base: usize,
+}
+
+#[derive(Default)]
+struct Linter {
+    sources: Vec<Source>,
+    /// with the offset they are at, to sort them.
+    diagnostics: Vec<(usize, Diagnostic)>,
+}
+
+impl Linter {
+    /// parses a file, with its offsets after those of the files before it.
+    fn parse(&mut self, name: &str, text: &str, format: Format) -> Option<Node> {
+        let base = self
+            .sources
+            .last()
+            .map(|source| source.base + source.text.len() + 1)
+            .unwrap_or(0);
+        self.sources.push(Source {
+            name: name.to_string(),
+            text: text.to_string(),
+            base,
+        });
+
+        match document::parse(text, format) {
+            Ok(mut node) => {
+                node.shift(base);
+                Some(node)
+            }
+            Err(e) => {
+                self.error(base + e.at, "", &e.message);
+                None
+            }
+        }
+    }
+
+    fn push(&mut self, severity: Severity, at: usize, path: &str, message: &str) {
+        let Some(source) = self.sources.iter().rev().find(|source| source.base <= at) else {
+            return;
+        };
+        let offset = (at - source.base).min(source.text.len());
+        let before = source.text.get(..offset).unwrap_or(&source.text);
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .map(|line| line.chars().count())
+            .unwrap_or(0)
+            + 1;
+
+        self.diagnostics.push((
+            at,
+            Diagnostic {
+                file: source.name.clone(),
+                line,
+                column,
+                severity,
+                path: path.to_string(),
+                message: message.to_string(),
+            },
+        ));
+    }
+
+    fn error(&mut self, at: usize, path: &str, message: &str) {
+        self.push(Severity::Error, at, path, message);
+    }
+
+    fn warning(&mut self, at: usize, path: &str, message: &str) {
+        self.push(Severity::Warning, at, path, message);
+    }
+
+    fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|(_, diagnostic)| diagnostic.severity == Severity::Error)
+    }
+
+    fn finish(mut self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .sort_by_key(|(at, diagnostic)| (diagnostic.severity, *at));
+        self.diagnostics.dedup();
+
+        self.diagnostics
+            .into_iter()
+            .map(|(_, diagnostic)| diagnostic)
+            .collect()
+    }
+
+    /// adds the templates and pipelines of the files `node` includes to it, the files they
+    /// include first. `stack` is the files being included, to catch cycles.
+    fn include(
+        &mut self,
+        node: &mut Node,
+        origin: &Origin,
+        includes: &Includes,
+        stack: &mut Vec<(Origin, PathBuf)>,
+    ) {
+        let Some(list) = node.get("include").cloned() else {
+            return;
+        };
+
+        for (i, entry) in list.elements().iter().enumerate() {
+            let path = format!("include[{i}]");
+            let at = entry.at.unwrap_or(0);
+
+            let include: Include = match serde_json::from_value(entry.to_json()) {
+                Ok(include) => include,
+                Err(e) => {
+                    // the schema checks the file being linted, included ones are only read.
+                    if stack.len() > 1 {
+                        self.error(at, &path, &e.to_string());
+                    }
+                    continue;
+                }
+            };
+            let origin = match origin.of(&include) {
+                Ok(origin) => origin,
+                Err(e) => {
+                    self.error(at, &path, &e.to_string());
+                    continue;
+                }
+            };
+            let name = origin.name(&include.file);
+            let file = normalize(&include.file);
+
+            if stack.iter().any(|(o, f)| *o == origin && *f == file) {
+                self.error(at, &path, &format!("`{name}` includes itself"));
+                continue;
+            }
+
+            let source = match includes.read(&origin, &include.file) {
+                Ok(source) => source,
+                Err(e) => {
+                    self.error(at, &path, &format!("failed to include {name}. {e}"));
+                    continue;
+                }
+            };
+            let Some(mut included) = self.parse(&name, &source, Format::of(&include.file)) else {
+                continue;
+            };
+
+            stack.push((origin.clone(), file));
+            self.include(&mut included, &origin, includes, stack);
+            stack.pop();
+
+            node.merge(&included, &["templates"]);
+        }
+    }
+
+    /// replaces every template and pipeline with `extends` with itself merged onto what it
+    /// extends.
+    fn extend(&mut self, root: &mut Node) {
+        let original = root.clone();
+        let mut done = BTreeMap::new();
+
+        let NodeValue::Table(entries) = &mut root.value else {
+            return;
+        };
+
+        for (key, _, node) in entries.iter_mut() {
+            match key.as_str() {
+                "templates" => {
+                    let NodeValue::Table(templates) = &mut node.value else {
+                        continue;
+                    };
+
+                    for (name, _, template) in templates.iter_mut() {
+                        let path = join("templates", name);
+                        *template = self.resolve(&original, path, template, &mut done, &mut vec![]);
+                    }
+                }
+                "include" => {}
+                _ => *node = self.resolve(&original, join("", key), node, &mut done, &mut vec![]),
+            }
+        }
+    }
+
+    /// `node` merged onto what it extends, which is resolved first. `done` has what's already
+    /// resolved by path and `visiting` what's being resolved, to catch cycles.
+    fn resolve(
+        &mut self,
+        root: &Node,
+        path: String,
+        node: &Node,
+        done: &mut BTreeMap<String, Node>,
+        visiting: &mut Vec<String>,
+    ) -> Node {
+        if let Some(node) = done.get(&path) {
+            return node.clone();
+        }
+
+        let mut node = node.clone();
+
+        if let Some(extends) = node.get("extends") {
+            let at = extends.at.unwrap_or(0);
+            let extends_path = join(&path, "extends");
+
+            // templates first, so a template can share its name with the pipeline using it.
+            let base = extends.as_str().and_then(|name| {
+                root.get("templates")
+                    .and_then(|templates| templates.get(name))
+                    .map(|base| (join("templates", name), base))
+                    .or_else(|| {
+                        root.get(name)
+                            .filter(|_| !RESERVED_KEYS.contains(&name))
+                            .map(|base| (join("", name), base))
+                    })
+            });
+
+            match (extends.as_str(), base) {
+                // the schema reports it.
+                (None, _) => {}
+                (Some(name), None) => {
+                    self.error(at, &extends_path, &format!("undefined template `{name}`"))
+                }
+                (Some(name), Some((base_path, _)))
+                    if base_path == path || visiting.contains(&base_path) =>
+                {
+                    self.error(at, &extends_path, &format!("`{name}` extends itself"))
+                }
+                (Some(_), Some((base_path, base))) => {
+                    visiting.push(path.clone());
+                    let base = self.resolve(root, base_path, base, done, visiting);
+                    visiting.pop();
+
+                    node.merge(&base, &["env"]);
+                }
+            }
+        }
+
+        done.insert(path, node.clone());
+        node
+    }
+
+    /// checks `node` against the part of the schema that describes it. only handles what
+    /// schemars generates for the pipeline types.
+    fn schema(&mut self, node: &Node, schema: &Json, root: &Json, path: &str, at: usize) {
+        let schema = resolve(schema, root);
+        // errors about the value point at it, missing keys at the key the table is under.
+        let value_at = node.at.unwrap_or(at);
+
+        if let Some(variants) = variants(schema, root) {
+            // the variant of the same type, so the errors are about what was meant.
+            match variants
+                .iter()
+                .find(|variant| accepts(variant, node.kind()))
+            {
+                Some(variant) => self.schema(node, variant, root, path, at),
+                None => {
+                    let expected: Vec<&str> = variants
+                        .iter()
+                        .flat_map(|variant| types(variant))
+                        .map(toml_kind)
+                        .collect();
+                    self.error(
+                        value_at,
+                        path,
+                        &format!(
+                            "expected {}, found {}",
+                            expected.join(" or "),
+                            toml_kind(node.kind())
+                        ),
+                    );
+                }
+            }
+
+            return;
+        }
+
+        if !types(schema).is_empty() && !accepts(schema, node.kind()) {
+            let expected: Vec<&str> = types(schema)
+                .into_iter()
+                .filter(|kind| *kind != "null")
+                .map(toml_kind)
+                .collect();
+            self.error(
+                value_at,
+                path,
+                &format!(
+                    "expected {}, found {}",
+                    expected.join(" or "),
+                    toml_kind(node.kind())
+                ),
+            );
+
+            return;
+        }
+
+        if let Some(allowed) = allowed_values(schema) {
+            let found = match node.as_str() {
+                Some(val) if allowed.contains(&val) => None,
+                Some(val) => Some(format!("`{val}`")),
+                None => Some(toml_kind(node.kind()).to_string()),
+            };
+
+            if let Some(found) = found {
+                let allowed: Vec<String> = allowed
+                    .iter()
+                    .map(|allowed| format!("`{allowed}`"))
+                    .collect();
+                self.error(
+                    value_at,
+                    path,
+                    &format!("expected one of {}, found {found}", allowed.join(", ")),
+                );
+            }
+        }
+
+        if let Some(val) = node.as_integer() {
+            let min = schema.get("minimum").and_then(|min| min.as_i64());
+            let max = schema.get("maximum").and_then(|max| max.as_i64());
+
+            match (min, max) {
+                (Some(min), _) if val < min => self.error(
+                    value_at,
+                    path,
+                    &format!("expected at least {min}, found {val}"),
+                ),
+                (_, Some(max)) if val > max => self.error(
+                    value_at,
+                    path,
+                    &format!("expected at most {max}, found {val}"),
+                ),
+                _ => {}
+            }
+        }
+
+        if node.kind() == "object" {
+            self.table(node, schema, root, path, at);
+        }
+
+        if let Some(items) = schema.get("items") {
+            for (i, element) in node.elements().iter().enumerate() {
+                let element_at = element.at.unwrap_or(value_at);
+                self.schema(element, items, root, &format!("{path}[{i}]"), element_at);
+            }
+        }
+    }
+
+    fn table(&mut self, node: &Node, schema: &Json, root: &Json, path: &str, at: usize) {
+        let properties = schema.get("properties").and_then(|props| props.as_object());
+        let entries = node.entries();
+
+        for (key, key_at, child) in entries.iter() {
+            let child_path = join(path, key);
+            let key_at = key_at.unwrap_or(at);
+
+            match (
+                properties.and_then(|props| props.get(key.as_str())),
+                schema.get("additionalProperties"),
+            ) {
+                (Some(prop), _) => self.schema(child, prop, root, &child_path, key_at),
+                (None, Some(Json::Bool(false))) => {
+                    let suggestion = properties
+                        .into_iter()
+                        .flat_map(|props| props.keys())
+                        .filter(|prop| distance(key, prop) <= 2)
+                        .min_by_key(|prop| distance(key, prop));
+                    let message = match suggestion {
+                        Some(prop) => format!("unknown key `{key}`, did you mean `{prop}`?"),
+                        None => format!("unknown key `{key}`"),
+                    };
+                    self.warning(key_at, &child_path, &message);
+                }
+                (None, Some(additional)) => {
+                    self.schema(child, additional, root, &child_path, key_at)
+                }
+                (None, None) => {}
+            }
+        }
+
+        for required in schema
+            .get("required")
+            .and_then(|required| required.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|required| required.as_str())
+        {
+            if !entries.iter().any(|(key, _, _)| *key == required) {
+                self.error(at, path, &format!("missing `{required}`"));
+            }
+        }
+    }
+
+    /// names a pipeline uses that aren't defined anywhere.
+    fn references(&mut self, root: &Node, pipelines: &Pipelines) {
+        let mut names: Vec<&String> = pipelines.keys().collect();
+        names.sort();
+
+        for name in names.iter() {
+            let pipeline = &pipelines[*name];
+            let Some(table) = root.get(name) else {
+                continue;
+            };
+            let pipeline_path = join("", name);
+            let at = table.at.unwrap_or(0);
+            let fields = table.entries();
+            let field = |field: &str| fields.iter().find(|(key, _, _)| *key == field);
+
+            if pipeline.approval.is_some() {
+                for (key, key_at, _) in fields.iter() {
+                    if STEP_KEYS.contains(&key.as_str()) {
+                        self.warning(
+                            key_at.unwrap_or(at),
+                            &join(&pipeline_path, key),
+                            &format!("approval jobs run no steps, `{key}` does nothing"),
+                        );
+                    }
+                }
+            } else {
+                for required in ["container", "script"] {
+                    if field(required).is_none() {
+                        self.error(at, &pipeline_path, &format!("missing `{required}`"));
+                    }
+                }
+            }
+
+            if let Some((_, _, needs)) = field("needs") {
+                for (i, (needed, element)) in pipeline
+                    .needs
+                    .iter()
+                    .flatten()
+                    .zip(needs.elements())
+                    .enumerate()
+                {
+                    if !pipelines.contains_key(needed) {
+                        let path = format!("{}[{i}]", join(&pipeline_path, "needs"));
+                        let at = element.at.unwrap_or(at);
+                        self.error(at, &path, &format!("undefined pipeline `{needed}`"));
+                    }
+                }
+            }
+
+            if let Some((_, _, inputs)) = field("inputs") {
+                for (name, name_at, node) in inputs.entries() {
+                    let Some(input) = pipeline.inputs.as_ref().and_then(|inputs| inputs.get(name))
+                    else {
+                        continue;
+                    };
+                    let path = join(&join(&pipeline_path, "inputs"), name);
+                    let at = name_at.unwrap_or(at);
+
+                    if name.is_empty()
+                        || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        self.error(
+                            at,
+                            &path,
+                            "input names can only have letters, digits and `_`",
+                        );
+                    }
+
+                    match (input.kind, &input.options) {
+                        (InputKind::Choice, None) => {
+                            self.error(at, &path, "`choice` inputs need `options`")
+                        }
+                        (InputKind::Choice, Some(_)) | (_, None) => {}
+                        (_, Some(_)) => {
+                            let at = node.get("options").and_then(|node| node.at).unwrap_or(at);
+                            self.warning(at, &path, "only `choice` inputs have `options`");
+                        }
+                    }
+
+                    if let Some(default) = &input.default {
+                        if let Err(e) = input.parse(&default.to_string()) {
+                            let at = node.get("default").and_then(|node| node.at).unwrap_or(at);
+                            self.error(
+                                at,
+                                &join(&path, "default"),
+                                &format!("invalid default, {e}"),
+                            );
+                        }
+                    }
+                }
+            }
+
+            if let Some((_, _, uses)) = field("uses_artifacts") {
+                for (i, (producer, element)) in pipeline
+                    .uses_artifacts
+                    .iter()
+                    .flatten()
+                    .zip(uses.elements())
+                    .enumerate()
+                {
+                    let path = format!("{}[{i}]", join(&pipeline_path, "uses_artifacts"));
+                    let at = element.at.unwrap_or(at);
+
+                    match pipelines.get(producer) {
+                        None => self.error(at, &path, &format!("undefined pipeline `{producer}`")),
+                        Some(p) if p.artifacts.is_none() => {
+                            self.error(at, &path, &format!("`{producer}` declares no artifacts"))
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+
+            let matrix: BTreeSet<String> = pipeline
+                .matrix
+                .iter()
+                .flat_map(|matrix| matrix.names())
+                .collect();
+            let mut conditions = Vec::new();
+
+            if let Some((_, _, condition)) = field("if") {
+                conditions.push((join(&pipeline_path, "if"), condition));
+            }
+
+            for key in ["script", "on_failure", "always"] {
+                let Some((_, _, steps)) = field(key) else {
+                    continue;
+                };
+
+                for (i, step) in steps.elements().iter().enumerate() {
+                    if let Some(condition) = step.get("if") {
+                        let path = format!("{}[{i}].if", join(&pipeline_path, key));
+                        conditions.push((path, condition));
+                    }
+                }
+            }
+
+            for (path, condition) in conditions {
+                let Some(expr) = condition.as_str() else {
+                    continue;
+                };
+                let at = condition.at.unwrap_or(at);
+
+                match Expr::parse(expr) {
+                    Ok(expr) => {
+                        for var in expr.matrix_vars() {
+                            if !matrix.contains(var) {
+                                self.error(
+                                    at,
+                                    &path,
+                                    &format!("undefined matrix variable `{var}`"),
+                                );
+                            }
+                        }
+                    }
+                    Err(e) => self.error(at, &path, &format!("invalid expression, {e}")),
+                }
+            }
+
+            let mut found = Vec::new();
+
+            for (key, _, child) in fields.iter().filter(|(key, _, _)| *key != "matrix") {
+                strings(child, &join(&pipeline_path, key), &mut found);
+            }
+
+            for (path, at, s) in found {
+                for var in matrix_refs(s) {
+                    if !matrix.contains(var) {
+                        self.error(at, &path, &format!("undefined matrix variable `{var}`"));
+                    }
+                }
+            }
+        }
+
+        if self.has_errors() {
+            return;
+        }
+
+        // reference errors above would be reported again, so only cycles are left.
+        for name in names {
+            if let Err(e) = job_order(pipelines, name) {
+                let at = root.get(name).and_then(|table| table.at).unwrap_or(0);
+                self.error(at, &join("", name), &e.to_string());
+                break;
+            }
+        }
+    }
+}
+
+/// `file` without `./`, so the same file is always the same path.
+fn normalize(file: impl AsRef<Path>) -> PathBuf {
+    file.as_ref()
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// the `NAME` of every `${matrix.NAME}` in `s`, skipping escaped ones.
+fn matrix_refs(s: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${matrix.") {
+        let escaped = rest[..start].ends_with('$');
+        rest = &rest[start + "${matrix.".len()..];
+
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        if !escaped {
+            refs.push(&rest[..end]);
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    refs
+}
+
+/// follows `$ref`s into the schemas `$defs`.
+fn resolve<'a>(schema: &'a Json, root: &'a Json) -> &'a Json {
+    match schema
+        .get("$ref")
+        .and_then(|path| path.as_str())
+        .and_then(|path| path.strip_prefix("#/$defs/"))
+        .and_then(|def| root.get("$defs")?.get(def))
+    {
+        Some(def) => resolve(def, root),
+        None => schema,
+    }
+}
+
+/// the variants of an `anyOf` schema besides `null`, with nested ones (like an `Option` of an
+/// untagged enum) flattened.
+fn variants<'a>(schema: &'a Json, root: &'a Json) -> Option<Vec<&'a Json>> {
+    let variants = schema
+        .get("anyOf")?
+        .as_array()?
+        .iter()
+        .map(|variant| resolve(variant, root))
+        .flat_map(|variant| variants(variant, root).unwrap_or(vec![variant]))
+        .filter(|variant| types(variant) != ["null"])
+        .collect();
+
+    Some(variants)
+}
+
+/// the json types a schema allows. empty if it doesn't say.
+fn types(schema: &Json) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Json::String(kind)) => vec![kind.as_str()],
+        Some(Json::Array(kinds)) => kinds.iter().filter_map(|kind| kind.as_str()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// the strings an enum schema allows. documented variants come as a `oneOf` of consts.
+fn allowed_values(schema: &Json) -> Option<Vec<&str>> {
+    if let Some(allowed) = schema.get("enum").and_then(|allowed| allowed.as_array()) {
+        return Some(
+            allowed
+                .iter()
+                .filter_map(|allowed| allowed.as_str())
+                .collect(),
+        );
+    }
+
+    schema
+        .get("oneOf")?
+        .as_array()?
+        .iter()
+        .map(|variant| variant.get("const")?.as_str())
+        .collect()
+}
+
+fn accepts(schema: &Json, kind: &str) -> bool {
+    types(schema)
+        .iter()
+        .any(|allowed| *allowed == kind || (*allowed == "number" && kind == "integer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ci_cd::Env;
+    use anyhow::anyhow;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    /// includes read from `files` instead of the repo.
+    fn includes<'a>(files: &'a [(&'a str, &'a str)]) -> Includes<'a> {
+        Includes::new(
+            move |file| {
+                files
+                    .iter()
+                    .find(|(name, _)| Path::new(name) == file)
+                    .map(|(_, source)| source.to_string())
+                    .ok_or_else(|| anyhow!("no such file {}", file.display()))
+            },
+            HashMap::new(),
+        )
+    }
+
+    fn lint_with(file: &str, source: &str, files: &[(&str, &str)]) -> Vec<String> {
+        lint(file, source, &includes(files))
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect()
+    }
+
+    fn lint_toml(source: &str) -> Vec<String> {
+        lint_with(".dcicd.toml", source, &[])
+    }
+
+    fn lint_yaml(source: &str) -> Vec<String> {
+        lint_with(".dcicd.yml", source, &[])
+    }
+
+    #[test]
+    fn valid_files_have_no_diagnostics() {
+        let toml = "[build]\ncontainer = \"alpine\"\nscript = [\"make\", { run = \"make test\", if = \"branch == 'main'\" }]\n";
+        let yaml = "build:\n  container: alpine\n  script:\n    - make\n    - run: make test\n      if: branch == 'main'\n";
+
+        assert_eq!(lint_toml(toml), Vec::<String>::new());
+        assert_eq!(lint_yaml(yaml), Vec::<String>::new());
+    }
+
+    #[test]
+    fn diagnostic_fields() {
+        let diagnostics = lint(
+            ".dcicd.yml",
+            "build:\n  container: 1\n  script: [make]\n",
+            &includes(&[]),
+        );
+
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                file: ".dcicd.yml".into(),
+                line: 2,
+                column: 14,
+                severity: Severity::Error,
+                path: "build.container".into(),
+                message: "expected a string, found an integer".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_suggest_close_ones() {
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nscipt = [\"make\"]\n"),
+            [
+                ".dcicd.toml:1:1: error: build: missing `script`",
+                ".dcicd.toml:3:1: warning: build.scipt: unknown key `scipt`, did you mean `script`?",
+            ]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script: [make]\n  colour: red\n"),
+            [".dcicd.yml:4:3: warning: build.colour: unknown key `colour`"]
+        );
+    }
+
+    #[test]
+    fn wrong_types() {
+        assert_eq!(
+            lint_toml("[build]\ncontainer = 1\nscript = [\"make\"]\n"),
+            [".dcicd.toml:2:13: error: build.container: expected a string, found an integer"]
+        );
+        // `Step` is an untagged enum, so the schema has a variant per type.
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script: [1]\n"),
+            [".dcicd.yml:3:12: error: build.script[0]: expected a string or a table, found an integer"]
+        );
+        // `Option<InputValue>` nests one `anyOf` in another.
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script: [make]\n  inputs:\n    x:\n      default: {a: 1}\n"),
+            [".dcicd.yml:6:16: error: build.inputs.x.default: expected a boolean or a number or a string, found a table"]
+        );
+    }
+
+    #[test]
+    fn enums_and_ranges() {
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script:\n    - run: make\n      shell: fish\n"),
+            [".dcicd.yml:5:14: error: build.script[0].shell: expected one of `sh`, `bash`, `python`, found `fish`"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script: [make]\n  inputs:\n    x:\n      type: text\n"),
+            [".dcicd.yml:6:13: error: build.inputs.x.type: expected one of `string`, `bool`, `choice`, `number`, found `text`"]
+        );
+        assert_eq!(
+            lint_yaml(
+                "build:\n  container: alpine\n  script: [make]\n  retry:\n    max_attempts: 0\n"
+            ),
+            [".dcicd.yml:5:19: error: build.retry.max_attempts: expected at least 1, found 0"]
+        );
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nscript = [\"make\"]\nretry = { max_attempts = 11 }\n"),
+            [".dcicd.toml:4:26: error: build.retry.max_attempts: expected at most 10, found 11"]
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nscript = [\"make\"\n"),
+            [".dcicd.toml:4:1: error: invalid array, expected `]`"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n script: [make]\n"),
+            [".dcicd.yml:3:8: error: while parsing a block mapping, did not find expected key"]
+        );
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        assert_eq!(
+            lint_yaml("build: {env: {É: \"é\"}, container: alpine, scipt: [make]}\n"),
+            [
+                ".dcicd.yml:1:8: error: build: missing `script`",
+                ".dcicd.yml:1:43: warning: build.scipt: unknown key `scipt`, did you mean `script`?",
+            ]
+        );
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nscript = [\"make\"]\nneeds = [\"tset\"]\n"),
+            [".dcicd.toml:4:10: error: build.needs[0]: undefined pipeline `tset`"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script: [make]\ntest:\n  container: alpine\n  script: [make]\n  uses_artifacts: [build]\n"),
+            [".dcicd.yml:7:20: error: test.uses_artifacts[0]: `build` declares no artifacts"]
+        );
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nscript = [\"make\"]\nif = \"branch = 'main'\"\n"),
+            [".dcicd.toml:4:6: error: build.if: invalid expression, unexpected `=`, did you mean `==`? (column 8)"]
+        );
+    }
+
+    #[test]
+    fn dependency_cycles() {
+        assert_eq!(
+            lint_yaml("a:\n  container: alpine\n  script: [make]\n  needs: [b]\nb:\n  container: alpine\n  script: [make]\n  needs: [a]\n"),
+            [".dcicd.yml:2:3: error: a: pipelines a -> b depend on each other"]
+        );
+    }
+
+    #[test]
+    fn matrix_variables() {
+        assert_eq!(
+            lint_toml("[build]\ncontainer = \"alpine\"\nmatrix = { os = [\"linux\"] }\nscript = [\"echo ${matrix.os} $${matrix.escaped} ${matrix.missing}\"]\n"),
+            [".dcicd.toml:4:11: error: build.script[0]: undefined matrix variable `missing`"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  container: alpine\n  script:\n    - run: make\n      if: matrix.os == 'linux'\n"),
+            [".dcicd.yml:5:11: error: build.script[0].if: undefined matrix variable `os`"]
+        );
+    }
+
+    #[test]
+    fn include_cycles() {
+        let files = [
+            ("a.yml", "include:\n  - file: b.yml\n"),
+            ("b.yml", "include:\n  - file: ./a.yml\n"),
+        ];
+
+        assert_eq!(
+            lint_with(
+                ".dcicd.yml",
+                "include:\n  - file: a.yml\nbuild:\n  container: alpine\n  script: [make]\n",
+                &files
+            ),
+            ["b.yml:2:5: error: include[0]: `./a.yml` includes itself"]
+        );
+        assert_eq!(
+            lint_with(
+                ".dcicd.yml",
+                "include:\n  - file: a.yml\n",
+                &[("a.yml", "include:\n  - file: .dcicd.yml\n")]
+            ),
+            ["a.yml:2:5: error: include[0]: `.dcicd.yml` includes itself"]
+        );
+        assert_eq!(
+            lint_yaml("include:\n  - file: nope.yml\n"),
+            [".dcicd.yml:2:5: error: include[0]: failed to include nope.yml. no such file nope.yml"]
+        );
+    }
+
+    #[test]
+    fn included_files_point_at_themselves() {
+        let files = [(
+            "shared.toml",
+            "[templates.rust]\ncontainer = \"rust\"\nscript = [1]\n",
+        )];
+
+        assert_eq!(
+            lint_with(
+                ".dcicd.yml",
+                "include:\n  - file: shared.toml\nbuild:\n  extends: rust\n",
+                &files
+            ),
+            [
+                "shared.toml:3:11: error: build.script[0]: expected a string or a table, found an integer",
+                "shared.toml:3:11: error: templates.rust.script[0]: expected a string or a table, found an integer",
+            ]
+        );
+    }
+
+    #[test]
+    fn extends_cycles() {
+        assert_eq!(
+            lint_yaml("templates:\n  a:\n    extends: b\n  b:\n    extends: a\nbuild:\n  extends: a\n  script: [make]\n"),
+            [".dcicd.yml:5:14: error: templates.b.extends: `a` extends itself"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  extends: build\n  container: alpine\n  script: [make]\n"),
+            [".dcicd.yml:2:12: error: build.extends: `build` extends itself"]
+        );
+        assert_eq!(
+            lint_yaml("build:\n  extends: nope\n  script: [make]\n"),
+            [".dcicd.yml:2:12: error: build.extends: undefined template `nope`"]
+        );
+    }
+
+    #[test]
+    fn extends_merges_env() {
+        let source = "[templates.base]\ncontainer = \"alpine\"\nenv = { A = \"a\", B = \"base\" }\n\n[build]\nextends = \"base\"\nscript = [\"make\"]\nenv = { B = \"build\" }\n";
+        let (pipelines, warnings) = load(".dcicd.toml", source, &includes(&[])).unwrap();
+        let build = &pipelines["build"];
+
+        assert!(warnings.is_empty());
+        assert_eq!(build.container, "alpine");
+        assert_eq!(
+            build.env,
+            Some(Env::from([
+                ("A".to_string(), "a".to_string()),
+                ("B".to_string(), "build".to_string()),
+            ]))
+        );
+        assert!(!pipelines.contains_key("templates"));
+    }
+
+    #[test]
+    fn errors_come_before_warnings() {
+        let diagnostics = lint_yaml("build:\n  colour: red\n  container: 1\n  script: [make]\n");
+
+        assert_eq!(
+            diagnostics,
+            [
+                ".dcicd.yml:3:14: error: build.container: expected a string, found an integer",
+                ".dcicd.yml:2:3: warning: build.colour: unknown key `colour`",
+            ]
+        );
+    }
+
+    #[test]
+    fn discord_report_cuts_off() {
+        let diagnostic = Diagnostic {
+            file: ".dcicd.toml".into(),
+            line: 1,
+            column: 1,
+            severity: Severity::Warning,
+            path: String::new(),
+            message: "x".into(),
+        };
+        let report = discord_report(&vec![diagnostic; DISCORD_DIAGNOSTICS + 2]);
+
+        assert!(report.starts_with("```\n.dcicd.toml:1:1: warning: x\n"));
+        assert!(report.ends_with("\n... and 2 more\n```"));
+        assert_eq!(report.lines().count(), DISCORD_DIAGNOSTICS + 3);
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(distance("script", "script"), 0);
+        assert_eq!(distance("scipt", "script"), 1);
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("", "env"), 3);
+        assert_eq!(distance("env", ""), 3);
+        assert_eq!(distance("é", "e"), 1);
+    }
+
+    #[test]
+    fn matrix_references() {
+        assert_eq!(matrix_refs("${matrix.os}-${matrix.node}"), ["os", "node"]);
+        assert_eq!(matrix_refs("$${matrix.os} ${matrix.node}"), ["node"]);
+        assert_eq!(matrix_refs("${matrix.os"), Vec::<&str>::new());
+        assert_eq!(matrix_refs("${env.HOME} $matrix.os"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(join("", "build"), "build");
+        assert_eq!(join("build", "script"), "build.script");
+        assert_eq!(join("build.env", "MY VAR"), "build.env.\"MY VAR\"");
+        assert_eq!(join("", ""), "\"\"");
+        assert_eq!(
+            normalize("./ci/../ci/./a.yml"),
+            PathBuf::from("ci/../ci/a.yml")
+        );
+    }
+
+    #[test]
+    fn schema_helpers() {
+        let root = json!({
+            "$defs": {
+                "Shell": {"type": "string", "enum": ["sh", "bash"]},
+                "Alias": {"$ref": "#/$defs/Shell"},
+                "Kind": {"oneOf": [
+                    {"type": "string", "const": "a", "description": "a"},
+                    {"type": "string", "const": "b"},
+                ]},
+                "Value": {"anyOf": [{"type": "boolean"}, {"type": "string"}]},
+            }
+        });
+
+        let alias = json!({"$ref": "#/$defs/Alias"});
+        let shell = resolve(&alias, &root);
+        assert_eq!(shell, &root["$defs"]["Shell"]);
+        assert_eq!(allowed_values(shell), Some(vec!["sh", "bash"]));
+        assert_eq!(allowed_values(&root["$defs"]["Kind"]), Some(vec!["a", "b"]));
+        assert_eq!(allowed_values(&json!({"type": "string"})), None);
+
+        let optional = json!({"anyOf": [{"$ref": "#/$defs/Value"}, {"type": "null"}]});
+        assert_eq!(
+            variants(&optional, &root),
+            Some(vec![
+                &json!({"type": "boolean"}),
+                &json!({"type": "string"})
+            ])
+        );
+        assert_eq!(variants(&json!({"type": "string"}), &root), None);
+
+        assert_eq!(
+            types(&json!({"type": ["string", "null"]})),
+            ["string", "null"]
+        );
+        assert!(accepts(&json!({"type": "number"}), "integer"));
+        assert!(!accepts(&json!({"type": "integer"}), "number"));
+    }
+}
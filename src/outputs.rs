@@ -0,0 +1,217 @@
+use crate::ci_cd::Env;
+
+/// the file steps write `key=value` lines to, one per output. multi-line values are written as
+/// `key<<DELIMITER`, the value's lines and a line with just `DELIMITER`.
+pub const OUTPUT_VAR: &str = "DCICD_OUTPUT";
+/// outputs are given to later steps and jobs as `DCICD_OUTPUT_<KEY>`.
+pub const OUTPUT_VAR_PREFIX: &str = "DCICD_OUTPUT_";
+
+/// the variable an output is given to later steps as.
+pub fn output_var(key: &str) -> String {
+    format!("{OUTPUT_VAR_PREFIX}{}", key.to_uppercase())
+}
+
+/// outputs as the variables later steps get.
+pub fn output_vars(outputs: &Env) -> Env {
+    outputs
+        .iter()
+        .map(|(key, value)| (output_var(key), value.clone()))
+        .collect()
+}
+
+fn is_key(key: &str) -> bool {
+    let key = key.trim();
+
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// reads the outputs a step wrote, later ones of the same key winning. lines that aren't one are
+/// returned as well, so they can be pointed out. so is the start of a multi-line value that's
+/// never closed.
+pub fn parse_outputs(s: &str) -> (Env, Vec<String>) {
+    let mut outputs = Env::new();
+    let mut invalid = Vec::new();
+    let mut lines = s.lines();
+
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=').filter(|(key, _)| is_key(key)) {
+            outputs.insert(key.trim().to_string(), value.to_string());
+            continue;
+        }
+
+        let heredoc = line
+            .split_once("<<")
+            .map(|(key, delimiter)| (key, delimiter.trim()))
+            .filter(|(key, delimiter)| is_key(key) && !delimiter.is_empty());
+
+        let Some((key, delimiter)) = heredoc else {
+            invalid.push(line.to_string());
+            continue;
+        };
+
+        let mut value = Vec::new();
+        let mut closed = false;
+
+        for line in lines.by_ref() {
+            if line.trim_end() == delimiter {
+                closed = true;
+                break;
+            }
+
+            value.push(line);
+        }
+
+        if closed {
+            outputs.insert(key.trim().to_string(), value.join("\n"));
+        } else {
+            invalid.push(line.to_string());
+        }
+    }
+
+    (outputs, invalid)
+}
+
+/// `key=value, ...` for logs and reports.
+pub fn fmt_outputs(outputs: &Env) -> String {
+    outputs
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ci_cd::{interpolate, Repo, RunContext, RunEvent},
+        expr::Expr,
+    };
+
+    fn env(pairs: &[(&str, &str)]) -> Env {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_key_value_lines() {
+        let (outputs, invalid) =
+            parse_outputs("version=1.2.0\n\n  image_tag = app:1.2 \nempty=\nurl=http://x/?a=b\n");
+
+        assert_eq!(
+            outputs,
+            env(&[
+                ("version", "1.2.0"),
+                // only the key is trimmed.
+                ("image_tag", " app:1.2 "),
+                ("empty", ""),
+                ("url", "http://x/?a=b"),
+            ])
+        );
+        assert!(invalid.is_empty());
+    }
+
+    #[test]
+    fn later_keys_win() {
+        let (outputs, _) = parse_outputs("version=1\nversion=2\n");
+
+        assert_eq!(outputs, env(&[("version", "2")]));
+    }
+
+    #[test]
+    fn returns_malformed_lines() {
+        let (outputs, invalid) =
+            parse_outputs("no equals sign\n=value\nbad key=1\nkey-with-dash=1\nok=1\r\n");
+
+        assert_eq!(outputs, env(&[("ok", "1")]));
+        assert_eq!(
+            invalid,
+            ["no equals sign", "=value", "bad key=1", "key-with-dash=1"]
+        );
+    }
+
+    #[test]
+    fn parses_multi_line_values() {
+        let (outputs, invalid) = parse_outputs(
+            "notes<<EOF\nfixed a=b\n\n  kept indented\nEOF\nafter=1\nempty<<END\nEND\n",
+        );
+
+        assert_eq!(
+            outputs,
+            env(&[
+                ("notes", "fixed a=b\n\n  kept indented"),
+                ("after", "1"),
+                ("empty", ""),
+            ])
+        );
+        assert!(invalid.is_empty());
+        // `=` comes first, so this is a value with `<<` in it.
+        assert_eq!(parse_outputs("cmd=a<<b").0, env(&[("cmd", "a<<b")]));
+    }
+
+    #[test]
+    fn returns_unclosed_multi_line_values() {
+        let (outputs, invalid) = parse_outputs("ok=1\nnotes<<EOF\nnever closed\n");
+
+        assert_eq!(outputs, env(&[("ok", "1")]));
+        assert_eq!(invalid, ["notes<<EOF"]);
+        // a delimiter is needed.
+        assert_eq!(parse_outputs("notes<<\n").1, ["notes<<"]);
+    }
+
+    #[test]
+    fn outputs_are_passed_on_as_variables() {
+        let (outputs, _) = parse_outputs("version=1.2\nimage_tag=app:1.2\n");
+        let vars = output_vars(&outputs);
+
+        assert_eq!(
+            vars,
+            env(&[
+                ("DCICD_OUTPUT_VERSION", "1.2"),
+                ("DCICD_OUTPUT_IMAGE_TAG", "app:1.2")
+            ])
+        );
+        // later steps read them with `${...}` and `if`s with `outputs.KEY`.
+        assert_eq!(
+            interpolate("docker push ${DCICD_OUTPUT_IMAGE_TAG}", &vars),
+            "docker push app:1.2"
+        );
+        assert!(Expr::parse("outputs.version == '1.2'")
+            .unwrap()
+            .holds(&vars, false));
+    }
+
+    #[test]
+    fn outputs_of_earlier_jobs_are_passed_on() {
+        let ctx = RunContext {
+            run_id: 1,
+            repo: Repo {
+                repo_name: "app".into(),
+                url: "https://example.com/app.git".parse().unwrap(),
+            },
+            commit_sha: "0".repeat(40),
+            branch: "main".into(),
+            tag: None,
+            pipeline_name: "deploy".into(),
+            triggered_by: "someone".into(),
+            event: RunEvent::Manual,
+            guild_id: None,
+            rerun_of: None,
+            channel_id: None,
+            inputs: Env::new(),
+            outputs: env(&[("version", "1.2")]),
+        };
+
+        assert_eq!(
+            ctx.vars().get("DCICD_OUTPUT_VERSION").map(String::as_str),
+            Some("1.2")
+        );
+        assert_eq!(fmt_outputs(&ctx.outputs), "version=1.2");
+    }
+}
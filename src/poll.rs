@@ -0,0 +1,347 @@
+use crate::{
+    ci_cd::{
+        pipeline_file, ChannelId, CiCdCmd, Env, QueuedRun, Repo, RepoName, RunEvent, STATE_DIR,
+    },
+    include::Includes,
+    lint,
+    secrets::GuildId,
+};
+use anyhow::{anyhow, Result};
+use crossbeam::channel::Sender;
+use git2::{Oid, Repository};
+use poise::serenity_prelude::futures::lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read_to_string, write},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    spawn,
+    task::{spawn_blocking, JoinHandle},
+    time::sleep,
+};
+use url::Url;
+
+pub const POLL_STATE_FILE: &str = "poll.json";
+pub const POLL_MIRROR_DIR: &str = "poll";
+
+/// polling config and last seen commits for a repo that can't send webhooks.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolledRepo {
+    pub url: Url,
+    /// seconds between fetches.
+    pub interval: u64,
+    pub branches: Vec<String>,
+    /// branch name -> last commit sha that was seen (and had its pipelines queued).
+    #[serde(default)]
+    pub last_seen: HashMap<String, String>,
+    /// the guild polling was set up from. its secrets are available to triggered runs.
+    #[serde(default)]
+    pub guild_id: Option<GuildId>,
+    /// the channel polling was set up from. triggered runs ask for approvals there.
+    #[serde(default)]
+    pub channel_id: Option<ChannelId>,
+    #[serde(skip)]
+    pub last_polled: Option<Instant>,
+}
+
+/// every polled repo. persisted to `STATE_DIR/poll.json` so restarts don't rebuild old commits.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct PollState {
+    pub repos: HashMap<RepoName, PolledRepo>,
+}
+
+impl PollState {
+    fn path() -> PathBuf {
+        let mut path = PathBuf::from(STATE_DIR);
+        path.push(POLL_STATE_FILE);
+
+        path
+    }
+
+    /// loads the saved poll state, starting fresh if there is none.
+    pub fn load() -> Self {
+        let Ok(state) = read_to_string(Self::path()) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&state).unwrap_or_else(|e| {
+            eprintln!("failed to parse {POLL_STATE_FILE}, starting fresh. {e}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        create_dir_all(STATE_DIR)?;
+        write(Self::path(), serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
+    /// starts (or reconfigures) polling a repo. keeps the last seen commits of branches that are
+    /// still watched.
+    pub fn watch(
+        &mut self,
+        repo_name: RepoName,
+        url: Url,
+        interval: u64,
+        branches: Vec<String>,
+        guild_id: Option<GuildId>,
+        channel_id: Option<ChannelId>,
+    ) {
+        let last_seen = self
+            .repos
+            .remove(&repo_name)
+            .map(|polled| polled.last_seen)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(branch, _)| branches.contains(branch))
+            .collect();
+
+        self.repos.insert(
+            repo_name,
+            PolledRepo {
+                url,
+                interval,
+                branches,
+                last_seen,
+                guild_id,
+                channel_id,
+                last_polled: None,
+            },
+        );
+    }
+
+    /// the clone urls of every polled repo.
+    pub fn urls(&self) -> HashMap<RepoName, Url> {
+        self.repos
+            .iter()
+            .map(|(name, polled)| (name.clone(), polled.url.clone()))
+            .collect()
+    }
+}
+
+/// fetches the watched branches into a bare mirror under `STATE_DIR` and returns their heads.
+fn fetch(repo_name: &str, polled: &PolledRepo) -> Result<(Repository, HashMap<String, Oid>)> {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(POLL_MIRROR_DIR);
+    path.push(format!("{repo_name}.git"));
+
+    let mirror = match Repository::open_bare(&path) {
+        Ok(mirror) => mirror,
+        Err(_) => Repository::init_bare(&path)?,
+    };
+
+    let refspecs: Vec<String> = polled
+        .branches
+        .iter()
+        .map(|branch| format!("+refs/heads/{branch}:refs/remotes/origin/{branch}"))
+        .collect();
+    mirror
+        .remote_anonymous(polled.url.as_str())?
+        .fetch(&refspecs, None, None)?;
+
+    let mut heads = HashMap::new();
+
+    for branch in polled.branches.iter() {
+        match mirror.refname_to_id(&format!("refs/remotes/origin/{branch}")) {
+            Ok(oid) => {
+                heads.insert(branch.clone(), oid);
+            }
+            Err(e) => eprintln!("{repo_name} has no branch {branch}. {e}"),
+        }
+    }
+
+    Ok((mirror, heads))
+}
+
+/// the pipelines at `commit` that are triggered by pushes to `branch`.
+fn triggered_pipelines(
+    mirror: &Repository,
+    commit: Oid,
+    branch: &str,
+    repos: &HashMap<RepoName, Url>,
+) -> Result<Vec<String>> {
+    let tree = mirror.find_commit(commit)?.tree()?;
+    let file = pipeline_file(|file| tree.get_name(file).is_some())
+        .map_err(|e| anyhow!("commit {commit}: {e}"))?;
+    let blob = tree
+        .get_name(file)
+        .ok_or_else(|| anyhow!("commit {commit} has no {file}"))?
+        .to_object(mirror)?
+        .peel_to_blob()?;
+    let source = String::from_utf8_lossy(blob.content()).to_string();
+    let includes = Includes::from_tree(mirror, tree, repos.clone());
+    let (pipelines, _) = lint::load(file, &source, &includes).map_err(|diagnostics| {
+        anyhow!(
+            "{file} at {commit} is invalid:\n{}",
+            lint::report(&diagnostics)
+        )
+    })?;
+
+    let mut names: Vec<String> = pipelines
+        .into_iter()
+        .filter(|(_, pipeline)| {
+            pipeline
+                .branches
+                .as_ref()
+                .is_some_and(|branches| branches.iter().any(|b| b == branch))
+        })
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    Ok(names)
+}
+
+/// polls one repo, queuing pipelines for new commits. `repos` are the ones its pipeline file can
+/// include from. returns true if the state changed.
+fn poll_repo(
+    repo_name: &str,
+    polled: &mut PolledRepo,
+    repos: &HashMap<RepoName, Url>,
+    send_cmd: &Sender<CiCdCmd>,
+) -> Result<bool> {
+    let (mirror, heads) = fetch(repo_name, polled)?;
+    let mut changed = false;
+
+    for (branch, head) in heads {
+        let head = head.to_string();
+
+        match polled.last_seen.get(&branch) {
+            Some(last) if *last == head => continue,
+            // first time seeing this branch, just remember where it is.
+            None => {}
+            Some(_) => match triggered_pipelines(&mirror, Oid::from_str(&head)?, &branch, repos) {
+                Ok(pipelines) => {
+                    for pipeline_name in pipelines {
+                        send_cmd.send(CiCdCmd::Enqueue(QueuedRun {
+                            repo: Repo {
+                                repo_name: repo_name.to_string(),
+                                url: polled.url.clone(),
+                            },
+                            branch: branch.clone(),
+                            commit: head.clone(),
+                            pipeline_name,
+                            guild_id: polled.guild_id,
+                            channel_id: polled.channel_id,
+                            triggered_by: "poller".into(),
+                            event: RunEvent::Push,
+                            rerun: None,
+                            // pushes run with the defaults.
+                            inputs: Env::new(),
+                            report: None,
+                        }))?;
+                    }
+                }
+                // a broken pipeline file shouldn't be retried every poll.
+                Err(e) => eprintln!("not queuing pipelines for {repo_name}@{head}. {e}"),
+            },
+        }
+
+        polled.last_seen.insert(branch, head);
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
+/// polls a due repo off the async runtime and saves what it found.
+async fn poll_due(
+    state: Arc<Mutex<PollState>>,
+    repo_name: RepoName,
+    mut polled: PolledRepo,
+    repos: HashMap<RepoName, Url>,
+    send_cmd: Sender<CiCdCmd>,
+) {
+    let polling = {
+        let repo_name = repo_name.clone();
+
+        spawn_blocking(move || {
+            let changed = poll_repo(&repo_name, &mut polled, &repos, &send_cmd);
+            (polled, changed)
+        })
+    };
+
+    let polled = match polling.await {
+        Ok((polled, Ok(true))) => polled,
+        Ok((_, Ok(false))) => return,
+        Ok((_, Err(e))) => {
+            eprintln!("failed to poll {repo_name}. {e}");
+            return;
+        }
+        Err(e) => {
+            eprintln!("failed to poll {repo_name}. {e}");
+            return;
+        }
+    };
+
+    let mut state = state.lock().await;
+
+    // it could have been reconfigured or removed while it was fetched.
+    let Some(current) = state.repos.get_mut(&repo_name) else {
+        return;
+    };
+
+    if current.url != polled.url {
+        return;
+    }
+
+    for (branch, head) in polled.last_seen {
+        if current.branches.contains(&branch) {
+            current.last_seen.insert(branch, head);
+        }
+    }
+
+    if let Err(e) = state.save() {
+        eprintln!("failed to save poll state. {e}");
+    }
+}
+
+pub async fn run_poller(state: Arc<Mutex<PollState>>, send_cmd: Sender<CiCdCmd>) {
+    // every repo is polled in its own task so a slow remote doesn't delay the others. a repo
+    // isn't polled again while its last poll is still going.
+    let mut polling: HashMap<RepoName, JoinHandle<()>> = HashMap::new();
+
+    loop {
+        sleep(Duration::from_secs(1)).await;
+        polling.retain(|_, poll| !poll.is_finished());
+
+        // the lock isn't held while fetching, so a slow remote doesn't hold up `/poll`.
+        let (due, repos) = {
+            let mut state = state.lock().await;
+            let repos = state.urls();
+            let due: Vec<(RepoName, PolledRepo)> = state
+                .repos
+                .iter_mut()
+                .filter(|(repo_name, polled)| {
+                    polled.interval != 0
+                        && !polling.contains_key(*repo_name)
+                        && !polled.last_polled.is_some_and(|last| {
+                            last.elapsed() < Duration::from_secs(polled.interval)
+                        })
+                })
+                .map(|(repo_name, polled)| {
+                    polled.last_polled = Some(Instant::now());
+                    (repo_name.clone(), polled.clone())
+                })
+                .collect();
+
+            (due, repos)
+        };
+
+        for (repo_name, polled) in due {
+            let poll = spawn(poll_due(
+                state.clone(),
+                repo_name.clone(),
+                polled,
+                repos.clone(),
+                send_cmd.clone(),
+            ));
+            polling.insert(repo_name, poll);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::ci_cd::{Env, RepoName, STATE_DIR};
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read, read_to_string, write, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+};
+
+pub type GuildId = u64;
+pub type SecretName = String;
+
+pub const SECRETS_FILE: &str = "secrets.json";
+pub const SECRETS_KEY_FILE: &str = "secrets.key";
+/// what secret values are replaced with in logs and discord messages.
+pub const MASK: &str = "***";
+
+/// where a secret is visible from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecretScope {
+    /// only pipelines of this repo.
+    Repo(RepoName),
+    /// every repo run from this guild.
+    Guild(GuildId),
+}
+
+/// encrypted secrets, base64 of nonce + ciphertext, as stored in `STATE_DIR/secrets.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+struct SealedSecrets {
+    #[serde(default)]
+    repos: BTreeMap<RepoName, BTreeMap<SecretName, String>>,
+    #[serde(default)]
+    guilds: BTreeMap<GuildId, BTreeMap<SecretName, String>>,
+}
+
+/// per repo and per guild secrets, encrypted at rest with a key kept in `STATE_DIR/secrets.key`.
+pub struct SecretStore {
+    cipher: ChaCha20Poly1305,
+    sealed: SealedSecrets,
+}
+
+fn state_path(file: &str) -> PathBuf {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(file);
+
+    path
+}
+
+/// reads the encryption key, generating one (readable only by us) on first use.
+fn load_key() -> Result<Key> {
+    let path = state_path(SECRETS_KEY_FILE);
+
+    if path.exists() {
+        let key = read(&path)?;
+
+        if key.len() != 32 {
+            bail!("{SECRETS_KEY_FILE} is corrupt, expected 32 bytes");
+        }
+
+        return Ok(*Key::from_slice(&key));
+    }
+
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    create_dir_all(STATE_DIR)?;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?
+        .write_all(&key)?;
+
+    Ok(key)
+}
+
+impl SecretStore {
+    pub fn load() -> Result<Self> {
+        let cipher = ChaCha20Poly1305::new(&load_key()?);
+        let sealed = match read_to_string(state_path(SECRETS_FILE)) {
+            Ok(sealed) => serde_json::from_str(&sealed)?,
+            Err(_) => SealedSecrets::default(),
+        };
+
+        Ok(Self { cipher, sealed })
+    }
+
+    fn save(&self) -> Result<()> {
+        create_dir_all(STATE_DIR)?;
+        write(
+            state_path(SECRETS_FILE),
+            serde_json::to_string_pretty(&self.sealed)?,
+        )?;
+
+        Ok(())
+    }
+
+    fn scope(&self, scope: &SecretScope) -> Option<&BTreeMap<SecretName, String>> {
+        match scope {
+            SecretScope::Repo(repo) => self.sealed.repos.get(repo),
+            SecretScope::Guild(guild) => self.sealed.guilds.get(guild),
+        }
+    }
+
+    fn scope_mut(&mut self, scope: &SecretScope) -> &mut BTreeMap<SecretName, String> {
+        match scope {
+            SecretScope::Repo(repo) => self.sealed.repos.entry(repo.clone()).or_default(),
+            SecretScope::Guild(guild) => self.sealed.guilds.entry(*guild).or_default(),
+        }
+    }
+
+    /// encrypts and saves a secret, replacing any old value.
+    pub fn set(&mut self, scope: &SecretScope, name: &str, value: &str) -> Result<()> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // the name is authenticated too so values can't be swapped between secrets.
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|e| anyhow!("failed to encrypt secret {name}. {e}"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+
+        self.scope_mut(scope)
+            .insert(name.to_string(), STANDARD.encode(sealed));
+        self.save()
+    }
+
+    /// removes a secret. returns false if it didn't exist.
+    pub fn remove(&mut self, scope: &SecretScope, name: &str) -> Result<bool> {
+        let removed = self.scope_mut(scope).remove(name).is_some();
+
+        if removed {
+            self.save()?;
+        }
+
+        Ok(removed)
+    }
+
+    /// the names (never the values) of the secrets in a scope.
+    pub fn names(&self, scope: &SecretScope) -> Vec<SecretName> {
+        self.scope(scope)
+            .map(|secrets| secrets.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn decrypt(&self, name: &str, sealed: &str) -> Result<String> {
+        let sealed = STANDARD.decode(sealed)?;
+
+        if sealed.len() < 12 {
+            bail!("secret {name} is corrupt");
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let value = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|e| anyhow!("failed to decrypt secret {name}. {e}"))?;
+
+        Ok(String::from_utf8(value)?)
+    }
+
+    /// decrypts the requested secrets. repo secrets shadow guild secrets of the same name. fails
+    /// if any of them are not set.
+    pub fn resolve(&self, repo: &str, guild: Option<GuildId>, names: &[SecretName]) -> Result<Env> {
+        let repo_scope = SecretScope::Repo(repo.to_string());
+        let guild_scope = guild.map(SecretScope::Guild);
+        let mut secrets = Env::new();
+        let mut missing = Vec::new();
+
+        for name in names {
+            let sealed = self
+                .scope(&repo_scope)
+                .and_then(|secrets| secrets.get(name))
+                .or_else(|| {
+                    guild_scope
+                        .as_ref()
+                        .and_then(|scope| self.scope(scope))
+                        .and_then(|secrets| secrets.get(name))
+                });
+
+            match sealed {
+                Some(sealed) => {
+                    secrets.insert(name.clone(), self.decrypt(name, sealed)?);
+                }
+                None => missing.push(name.clone()),
+            }
+        }
+
+        if !missing.is_empty() {
+            bail!("secrets not set for {repo}: {}", missing.join(", "));
+        }
+
+        Ok(secrets)
+    }
+}
+
+/// replaces every secret value in `text` with `MASK`.
+pub fn mask(text: &str, secrets: &Env) -> String {
+    let mut values: Vec<&String> = secrets.values().filter(|val| !val.is_empty()).collect();
+    // longest first so a secret containing another is masked whole.
+    values.sort_by_key(|val| std::cmp::Reverse(val.len()));
+
+    values.into_iter().fold(text.to_string(), |text, val| {
+        text.replace(val.as_str(), MASK)
+    })
+}
@@ -0,0 +1,288 @@
+use crate::ci_cd::{Env, PipelineName, RunId, STATE_DIR};
+use anyhow::{bail, Result};
+use docker_command::{CreateNetworkOpt, Launcher, RunOpt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{create_dir_all, read_dir, write},
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+pub type ServiceName = String;
+
+pub const SERVICES_DIR: &str = "services";
+/// seconds between health checks by default.
+pub const DEFAULT_HEALTH_INTERVAL: u64 = 2;
+/// seconds to wait for a service to become healthy by default.
+pub const DEFAULT_HEALTH_TIMEOUT: u64 = 60;
+
+/// a container started next to a job, e.g. a database for integration tests. steps reach it by
+/// its name as the hostname.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct Service {
+    pub image: String,
+    pub env: Option<Env>,
+    /// ports the service listens on. the first is given to steps as `DCICD_SERVICE_<NAME>_PORT`.
+    pub ports: Option<Vec<u16>>,
+    /// how to tell the service is ready. without one the images own `HEALTHCHECK` is waited on,
+    /// if it has one.
+    pub health_check: Option<HealthCheck>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct HealthCheck {
+    /// run in the service container with `sh -c`. the service is healthy once it exits 0.
+    pub command: String,
+    /// seconds between checks.
+    pub interval: Option<u64>,
+    /// seconds to wait before giving up.
+    pub timeout: Option<u64>,
+}
+
+/// a service container started for a job.
+struct Container {
+    job: PipelineName,
+    service: ServiceName,
+    name: String,
+}
+
+/// the service containers of a run and the network they share with the runner.
+pub struct RunServices {
+    run_id: RunId,
+    /// created when the first service starts.
+    network: Option<String>,
+    containers: Vec<Container>,
+}
+
+/// where a services logs are saved once its job is done.
+fn log_path(run_id: RunId, job: &str, service: &str) -> PathBuf {
+    let mut path = PathBuf::from(STATE_DIR);
+    path.push(SERVICES_DIR);
+    path.push(run_id.to_string());
+    path.push(job);
+    path.push(format!("{service}.log"));
+
+    path
+}
+
+/// the saved logs of a service. `job` is only needed if more than one job ran the service.
+pub fn logs(run_id: RunId, service: &str, job: Option<&str>) -> Result<PathBuf> {
+    if let Some(job) = job {
+        let path = log_path(run_id, job, service);
+
+        if !path.exists() {
+            bail!("{job} of run {run_id} had no service {service}");
+        }
+
+        return Ok(path);
+    }
+
+    let mut dir = PathBuf::from(STATE_DIR);
+    dir.push(SERVICES_DIR);
+    dir.push(run_id.to_string());
+
+    let Ok(jobs) = read_dir(&dir) else {
+        bail!("run {run_id} had no services");
+    };
+
+    let found: Vec<PathBuf> = jobs
+        .filter_map(|job| job.ok())
+        .map(|job| job.path().join(format!("{service}.log")))
+        .filter(|path| path.exists())
+        .collect();
+
+    match found.as_slice() {
+        [] => bail!("run {run_id} had no service {service}"),
+        [path] => Ok(path.clone()),
+        _ => bail!("more than one job of run {run_id} had a service {service}, pick a job"),
+    }
+}
+
+/// env vars telling steps where the services are.
+pub fn service_vars(services: &BTreeMap<ServiceName, Service>) -> Env {
+    let mut vars = Env::new();
+
+    for (name, service) in services.iter() {
+        let var = name.to_uppercase().replace('-', "_");
+        vars.insert(format!("DCICD_SERVICE_{var}_HOST"), name.clone());
+
+        if let Some(port) = service.ports.iter().flatten().next() {
+            vars.insert(format!("DCICD_SERVICE_{var}_PORT"), port.to_string());
+        }
+    }
+
+    vars
+}
+
+/// checks once if a service is ready.
+fn is_healthy(launcher: &Launcher, container: &str, check: Option<&HealthCheck>) -> bool {
+    let mut cmd = launcher.base_command().clone();
+
+    match check {
+        Some(check) => {
+            cmd.add_arg_pair("exec", container)
+                .add_arg_pair("sh", "-c")
+                .add_arg(&check.command);
+        }
+        None => {
+            cmd.add_arg_pair("inspect", "--format")
+                .add_arg("{{if .State.Health}}{{.State.Health.Status}}{{else}}healthy{{end}}")
+                .add_arg(container);
+        }
+    }
+
+    cmd.enable_capture();
+    cmd.log_command = false;
+    cmd.log_output_on_error = false;
+
+    match cmd.run() {
+        Ok(res) if check.is_none() => res.stdout_string_lossy().trim() == "healthy",
+        Ok(_) => true,
+        Err(_) => false,
+    }
+}
+
+fn wait_healthy(launcher: &Launcher, container: &str, check: Option<&HealthCheck>) -> Result<()> {
+    let interval = check
+        .and_then(|check| check.interval)
+        .unwrap_or(DEFAULT_HEALTH_INTERVAL);
+    let timeout = check
+        .and_then(|check| check.timeout)
+        .unwrap_or(DEFAULT_HEALTH_TIMEOUT);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    while !is_healthy(launcher, container, check) {
+        if Instant::now() >= deadline {
+            bail!("{container} did not become healthy within {timeout}s");
+        }
+
+        sleep(Duration::from_secs(interval));
+    }
+
+    Ok(())
+}
+
+impl RunServices {
+    pub fn new(run_id: RunId) -> Self {
+        Self {
+            run_id,
+            network: None,
+            containers: Vec::new(),
+        }
+    }
+
+    /// the network the runner has to join to reach the services.
+    pub fn network(&self) -> Option<String> {
+        self.network.clone()
+    }
+
+    /// starts a jobs services and waits for them to become healthy.
+    pub fn start(
+        &mut self,
+        launcher: &Launcher,
+        job: &str,
+        services: &BTreeMap<ServiceName, Service>,
+    ) -> Result<()> {
+        let network = match &self.network {
+            Some(network) => network.clone(),
+            None => {
+                let network = format!("dcicd-{}", self.run_id);
+                launcher
+                    .create_network(CreateNetworkOpt {
+                        name: network.clone(),
+                    })
+                    .run()?;
+                self.network = Some(network.clone());
+
+                network
+            }
+        };
+
+        for (service, config) in services.iter() {
+            let name = format!("dcicd-{}-{job}-{service}", self.run_id);
+            let mut cmd = launcher.run(RunOpt {
+                image: config.image.clone(),
+                env: config
+                    .env
+                    .iter()
+                    .flatten()
+                    .map(|(name, val)| (name.into(), val.into()))
+                    .collect(),
+                detach: true,
+                name: Some(name.clone()),
+                network: Some(network.clone()),
+                ..Default::default()
+            });
+            // reachable from the steps by the service name.
+            cmd.args.insert(1, "--network-alias".into());
+            cmd.args.insert(2, service.into());
+            cmd.enable_capture();
+
+            if let Err(e) = cmd.run() {
+                bail!("failed to start service {service}. {e}");
+            }
+
+            self.containers.push(Container {
+                job: job.to_string(),
+                service: service.clone(),
+                name: name.clone(),
+            });
+
+            println!("waiting for service {service} to become healthy");
+            wait_healthy(launcher, &name, config.health_check.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    /// saves the logs of the running services and removes their containers.
+    pub fn stop(&mut self, launcher: &Launcher) {
+        for container in self.containers.drain(..) {
+            let mut logs = launcher.base_command().clone();
+            logs.add_arg_pair("logs", &container.name)
+                .combine_output()
+                .enable_capture();
+
+            match logs.run() {
+                Ok(res) => {
+                    let path = log_path(self.run_id, &container.job, &container.service);
+
+                    if let Err(e) = path
+                        .parent()
+                        .map(create_dir_all)
+                        .unwrap_or(Ok(()))
+                        .and_then(|_| write(&path, &res.stdout))
+                    {
+                        eprintln!("failed to save logs of {}. {e}", container.service);
+                    }
+                }
+                Err(e) => eprintln!("failed to get logs of {}. {e}", container.service),
+            }
+
+            let mut remove = launcher.base_command().clone();
+            remove
+                .add_arg_pair("rm", "--force")
+                .add_arg(&container.name);
+
+            if let Err(e) = remove.run() {
+                eprintln!("failed to remove service container {}. {e}", container.name);
+            }
+        }
+    }
+
+    /// stops every service and removes the network.
+    pub fn teardown(mut self, launcher: &Launcher) {
+        self.stop(launcher);
+
+        if let Some(network) = &self.network {
+            if let Err(e) = launcher.remove_network(network).run() {
+                eprintln!("failed to remove network {network}. {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{ci_cd::matrix_label, history::JobRecord};
+
+/// the file steps append markdown to, posted to discord with the run report.
+pub const SUMMARY_VAR: &str = "DCICD_SUMMARY";
+/// the most text a discord embed can have.
+pub const MAX_EMBED_LEN: usize = 4096;
+/// the most embeds a summary is posted as, the rest of a longer one is cut off.
+pub const MAX_EMBEDS: usize = 10;
+/// opens and closes markdown code blocks.
+const FENCE: &str = "```";
+
+/// the markdown every step of a run wrote. headed by its job and matrix combination when there's
+/// more than one to tell apart.
+pub fn collect(jobs: &[JobRecord]) -> Option<String> {
+    let jobs: Vec<(&JobRecord, Vec<String>)> = jobs
+        .iter()
+        .map(|job| {
+            let cells = job
+                .cells
+                .iter()
+                .filter_map(|cell| {
+                    let markdown = cell
+                        .steps
+                        .iter()
+                        .filter_map(|step| step.markdown.as_deref())
+                        .map(str::trim_end)
+                        .collect::<Vec<_>>()
+                        .join("\n\n");
+
+                    if markdown.is_empty() {
+                        None
+                    } else if cell.matrix.is_empty() {
+                        Some(markdown)
+                    } else {
+                        Some(format!("### {}\n{markdown}", matrix_label(&cell.matrix)))
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            (job, cells)
+        })
+        .filter(|(_, cells)| !cells.is_empty())
+        .collect();
+
+    let summary = jobs
+        .iter()
+        .map(|(job, cells)| match jobs.len() {
+            1 => cells.join("\n\n"),
+            _ => format!("## {}\n{}", job.name, cells.join("\n\n")),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    Some(summary).filter(|summary| !summary.is_empty())
+}
+
+/// splits a summary into parts of at most `max` characters, between lines where it can. a code
+/// block split across parts is closed at the end of one and opened again in the next.
+pub fn split(summary: &str, max: usize) -> Vec<String> {
+    let len = |s: &str| s.chars().count();
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    // whether the part has any lines besides the one opening the code block it continues.
+    let mut has_lines = false;
+    // the line that opened the code block the next line is in.
+    let mut fence: Option<String> = None;
+    // where that line starts in the part, while nothing came after it.
+    let mut opened_at: Option<usize> = None;
+
+    for line in summary.lines() {
+        let toggles = line.trim_start().starts_with(FENCE);
+        // a part that ends in a code block needs room to close it.
+        let reserved = if toggles != fence.is_some() {
+            FENCE.len() + 1
+        } else {
+            0
+        };
+        let mut line = line.to_string();
+
+        loop {
+            let sep = usize::from(!part.is_empty());
+
+            // an empty line only doesn't fit if `max` is too small for the code block around it.
+            if len(&part) + sep + len(&line) + reserved <= max || (line.is_empty() && !has_lines) {
+                break;
+            }
+
+            // lines too long for a part of their own are cut wherever.
+            if !has_lines {
+                let room = max.saturating_sub(len(&part) + sep + reserved).max(1);
+                let rest =
+                    line.split_off(line.char_indices().nth(room).map_or(line.len(), |(i, _)| i));
+
+                if sep == 1 {
+                    part.push('\n');
+                }
+
+                part.push_str(&line);
+                line = rest;
+            }
+
+            // a code block opened at the end of the part is moved to the next one instead.
+            let mut full = std::mem::replace(&mut part, fence.clone().unwrap_or_default());
+
+            match opened_at.take() {
+                Some(at) => full.truncate(at),
+                None if fence.is_some() => {
+                    full.push('\n');
+                    full.push_str(FENCE);
+                }
+                None => {}
+            }
+
+            parts.push(full);
+            has_lines = false;
+        }
+
+        opened_at = (toggles && fence.is_none()).then_some(part.len());
+
+        if !part.is_empty() {
+            part.push('\n');
+        }
+
+        part.push_str(&line);
+        has_lines = true;
+
+        if toggles {
+            fence = match fence {
+                Some(_) => None,
+                None => Some(line.trim().to_string()),
+            };
+        }
+    }
+
+    if has_lines {
+        parts.push(part);
+    }
+
+    parts
+        .into_iter()
+        .map(|part| part.trim_end().to_string())
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths(parts: &[String]) -> Vec<usize> {
+        parts.iter().map(|part| part.chars().count()).collect()
+    }
+
+    #[test]
+    fn splits_between_lines() {
+        assert_eq!(split("one\ntwo\nthree", 7), ["one\ntwo", "three"]);
+        assert_eq!(split("one\ntwo\nthree", 100), ["one\ntwo\nthree"]);
+        assert_eq!(split("one\n\n\ntwo\n", 4), ["one", "two"]);
+        assert!(split("", 10).is_empty());
+    }
+
+    #[test]
+    fn cuts_lines_longer_than_a_part() {
+        assert_eq!(
+            split("short\nabcdefghijklmnopqrstuvwxyz\nend", 10),
+            ["short", "abcdefghij", "klmnopqrst", "uvwxyz\nend"]
+        );
+    }
+
+    #[test]
+    fn cuts_between_characters() {
+        let summary = "ééééé🦀🦀🦀🦀🦀\n日本語のテキスト";
+        let parts = split(summary, 4);
+
+        assert_eq!(parts, ["éééé", "é🦀🦀🦀", "🦀🦀", "日本語の", "テキスト"]);
+        assert!(lengths(&parts).iter().all(|len| *len <= 4));
+        assert_eq!(parts.concat(), summary.replace('\n', ""));
+    }
+
+    #[test]
+    fn reopens_code_blocks_split_across_parts() {
+        let summary = "results:\n```rust\nlet a = 1;\nlet b = 2;\nlet c = 3;\n```\ndone";
+        let parts = split(summary, 34);
+
+        assert_eq!(
+            parts,
+            [
+                "results:\n```rust\nlet a = 1;\n```",
+                "```rust\nlet b = 2;\nlet c = 3;\n```",
+                "done",
+            ]
+        );
+        assert!(lengths(&parts).iter().all(|len| *len <= 34));
+    }
+
+    #[test]
+    fn moves_code_blocks_opened_at_the_end_of_a_part() {
+        let parts = split("results:\n```rust\nlet a = 1;\nlet b = 2;\n```", 30);
+
+        assert_eq!(
+            parts,
+            [
+                "results:",
+                "```rust\nlet a = 1;\n```",
+                "```rust\nlet b = 2;\n```"
+            ]
+        );
+    }
+
+    #[test]
+    fn cuts_long_lines_in_code_blocks() {
+        let parts = split("```\n0123456789abcdefghij\n```", 16);
+
+        assert_eq!(
+            parts,
+            ["```\n01234567\n```", "```\n89abcdef\n```", "```\nghij\n```"]
+        );
+        assert!(lengths(&parts).iter().all(|len| *len <= 16));
+    }
+
+    #[test]
+    fn leaves_code_blocks_that_fit_alone() {
+        assert_eq!(
+            split("intro line\n```\ncode\n```", 14),
+            ["intro line", "```\ncode\n```"]
+        );
+    }
+}